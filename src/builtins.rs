@@ -0,0 +1,579 @@
+// Commands the shell runs by itself. Every one of them is a type implementing `Builtin` and listed in
+// `BUILTINS`, that list is what `run_command`, suggestions and `type` all look at.
+
+use std::{env, fs, io, process, thread};
+use std::io::{Read, Write};
+use std::sync::mpsc;
+
+use crossterm::cursor;
+use crossterm::style::Stylize;
+
+use crate::exes::{self, NameCase};
+use crate::{printf, Shell, TermClearer, UQueueable, UWrite};
+
+/*
+ * ===================================
+ * | Plumbing
+ * -----------------------------------
+ */
+
+/// Where a command reads from
+pub enum Input {
+    Terminal,
+    File(fs::File),
+    Pipe(io::PipeReader),
+}
+
+/// Where a command writes to
+pub enum Output {
+    Stdout,
+    Stderr,
+    File(fs::File),
+    /// The next command in a pipeline. Writes go through a thread so a builtin never gets stuck on a
+    /// pipe nobody is reading yet, `pipe` is there for programs to write into directly. Once the reader
+    /// is gone the thread stops and writes fail with BrokenPipe.
+    Pipe { pipe: io::PipeWriter, relay: mpsc::Sender<Vec<u8>> },
+}
+
+/// The stdin, stdout and stderr a command gets, the terminal unless redirected or piped
+pub struct Io {
+    pub stdin: Input,
+    pub stdout: Output,
+    pub stderr: Output,
+}
+
+impl Default for Io {
+    fn default() -> Self {
+        return Io { stdin: Input::Terminal, stdout: Output::Stdout, stderr: Output::Stderr };
+    }
+}
+
+/// A pipe between two commands, the read end goes to the one on the right
+pub fn pipe() -> Result<(Input, Output), String> {
+    let (reader, writer) = match io::pipe() {
+        Ok(x) => x,
+        Err(err) => return Err(format!("Failed to create pipe: {}", err)),
+    };
+    let mut relayed = match writer.try_clone() {
+        Ok(x) => x,
+        Err(err) => return Err(format!("Failed to create pipe: {}", err)),
+    };
+    let (relay, chunks) = mpsc::channel::<Vec<u8>>();
+    thread::spawn(move || {
+        for chunk in chunks {
+            // Whoever was reading is gone, the rest of the output is going nowhere
+            if relayed.write_all(&chunk).is_err() {
+                break;
+            }
+        }
+    });
+    return Ok((Input::Pipe(reader), Output::Pipe { pipe: writer, relay }));
+}
+
+impl Input {
+    pub fn try_clone(&self) -> Result<Input, String> {
+        let res = match self {
+            Input::Terminal => return Ok(Input::Terminal),
+            Input::File(f) => f.try_clone().map(Input::File),
+            Input::Pipe(p) => p.try_clone().map(Input::Pipe),
+        };
+        return res.map_err(|err| format!("Failed to duplicate stdin: {}", err));
+    }
+
+    fn stdio(&self) -> Result<process::Stdio, String> {
+        let res = match self {
+            Input::Terminal => return Ok(process::Stdio::inherit()),
+            Input::File(f) => f.try_clone().map(process::Stdio::from),
+            Input::Pipe(p) => p.try_clone().map(process::Stdio::from),
+        };
+        return res.map_err(|err| format!("Failed to duplicate stdin: {}", err));
+    }
+}
+
+impl Read for Input {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        return match self {
+            Input::Terminal => io::stdin().read(buf),
+            Input::File(f) => f.read(buf),
+            Input::Pipe(p) => p.read(buf),
+        };
+    }
+}
+
+impl Output {
+    pub fn try_clone(&self) -> Result<Output, String> {
+        let res = match self {
+            Output::Stdout => return Ok(Output::Stdout),
+            Output::Stderr => return Ok(Output::Stderr),
+            Output::File(f) => f.try_clone().map(Output::File),
+            Output::Pipe { pipe, relay } => pipe.try_clone().map(|pipe| Output::Pipe { pipe, relay: relay.clone() }),
+        };
+        return res.map_err(|err| format!("Failed to duplicate output: {}", err));
+    }
+
+    fn stdio(&self) -> Result<process::Stdio, String> {
+        let res = match self {
+            Output::Stdout | Output::Stderr => return Ok(process::Stdio::inherit()),
+            Output::File(f) => f.try_clone().map(process::Stdio::from),
+            Output::Pipe { pipe, .. } => pipe.try_clone().map(process::Stdio::from),
+        };
+        return res.map_err(|err| format!("Failed to duplicate output: {}", err));
+    }
+
+    /// Whether this goes straight to the shell's own stdout or stderr
+    pub fn is_terminal(&self) -> bool {
+        return matches!(self, Output::Stdout | Output::Stderr);
+    }
+
+    /// Whether there's a command on the other end that's running at the same time
+    pub fn is_pipe(&self) -> bool {
+        return matches!(self, Output::Pipe { .. });
+    }
+
+    /// Whether this is a pipe whose reader already went away, nothing written to it goes anywhere
+    pub fn is_closed(&self) -> bool {
+        return match self {
+            Output::Pipe { relay, .. } => relay.send(Vec::new()).is_err(),
+            _ => false,
+        };
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        return match self {
+            Output::Stdout => io::stdout().write(buf),
+            Output::Stderr => io::stderr().write(buf),
+            Output::File(f) => f.write(buf),
+            Output::Pipe { relay, .. } => match relay.send(buf.to_vec()) {
+                Ok(_) => Ok(buf.len()),
+                Err(_) => Err(io::Error::from(io::ErrorKind::BrokenPipe)),
+            },
+        };
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return match self {
+            Output::Stdout => io::stdout().flush(),
+            Output::Stderr => io::stderr().flush(),
+            Output::File(f) => f.flush(),
+            Output::Pipe { .. } => Ok(()),
+        };
+    }
+}
+
+impl Io {
+    pub fn try_clone(&self) -> Result<Io, String> {
+        return Ok(Io {
+            stdin: self.stdin.try_clone()?,
+            stdout: self.stdout.try_clone()?,
+            stderr: self.stderr.try_clone()?,
+        });
+    }
+
+    /// Hands the same stdin, stdout and stderr to a program about to be spawned
+    pub fn apply(&self, req: &mut process::Command) -> Result<(), String> {
+        req.stdin(self.stdin.stdio()?).stdout(self.stdout.stdio()?).stderr(self.stderr.stdio()?);
+        return Ok(());
+    }
+}
+
+/*
+ * ===================================
+ * | Registry
+ * -----------------------------------
+ */
+
+pub trait Builtin: Sync {
+    /// What it's called, the name it's listed under
+    fn name(&self) -> &'static str;
+    /// Other names that run the exact same thing
+    fn aliases(&self) -> &'static [&'static str] {
+        return &[];
+    }
+    /// What it does and how to use it, the first line is a one line summary
+    fn help(&self) -> &'static str;
+    /// How its arguments complete, flags the way `complete` takes them. None leaves it to files.
+    fn completion(&self) -> Option<&'static [&'static str]> {
+        return None;
+    }
+    /// `argv[0]` is whichever name it got called by, `env_vars` are the `NAME=value`s in front of it.
+    /// Returns the exit status. `Err` is for writes that failed, when those went to a file or a pipe the
+    /// command just fails with a 1, only losing the terminal takes the shell down.
+    fn run(&self, shell: &mut Shell, argv: &[String], env_vars: &[(String, String)], io: &mut Io) -> Result<i32, String>;
+}
+
+pub static BUILTINS: &[&dyn Builtin] = &[
+    &Cd,
+    &Clear,
+    &Command,
+    &Complete,
+    &Debug,
+    &Echo,
+    &Exit,
+    &Help,
+    &Printf,
+    &Rehash,
+    &Time,
+    &Type,
+];
+
+/// The builtin that runs for `name`, if any
+pub fn find(name: &str, case: NameCase) -> Option<&'static dyn Builtin> {
+    return BUILTINS.iter().copied().find(|x| case.eq(x.name(), name) || x.aliases().iter().any(|alias| case.eq(alias, name)));
+}
+
+/// Every name a builtin can be called by, aliases included
+pub fn names() -> impl Iterator<Item = &'static str> {
+    return BUILTINS.iter().flat_map(|x| std::iter::once(x.name()).chain(x.aliases().iter().copied()));
+}
+
+/// Every name `builtin` goes by, `cd, chdir`
+fn label(builtin: &dyn Builtin) -> String {
+    let mut label = builtin.name().to_string();
+    for alias in builtin.aliases() {
+        label.push_str(", ");
+        label.push_str(alias);
+    }
+    return label;
+}
+
+/// The full help of `builtin`, what `help name` and `name --help` print
+pub fn write_help(out: &mut impl Write, builtin: &dyn Builtin) -> Result<(), String> {
+    let mut lines = builtin.help().lines();
+    out.uswrite(label(builtin).bold())?;
+    out.ubwrite(format!(" - {}\n", lines.next().unwrap_or_default()))?;
+    for line in lines {
+        out.ubwrite(format!("{}\n", line))?;
+    }
+    return Ok(());
+}
+
+/// Complains about how the builtin `name` got called, with the usage lines from its help
+pub fn usage_error(io: &mut Io, name: &str) -> Result<i32, String> {
+    let help = find(name, NameCase::Sensitive).map(|x| x.help()).unwrap_or_default();
+    io.stderr.uswrite("[?ERROR]".red())?;
+    let usage = help.lines().skip_while(|x| !x.starts_with("Usage:")).take_while(|x| x.starts_with("Usage:") || x.starts_with(' '));
+    for (i, line) in usage.enumerate() {
+        // Lined up under the first one, past the `[?ERROR]`
+        let indent = if i == 0 { " " } else { "         " };
+        io.stderr.ubwrite(format!("{}{}\n", indent, line))?;
+    }
+    return Ok(2);
+}
+
+/*
+ * ===================================
+ * | Builtins
+ * -----------------------------------
+ */
+
+struct Cd;
+impl Builtin for Cd {
+    fn name(&self) -> &'static str { return "cd"; }
+    fn aliases(&self) -> &'static [&'static str] { return &["chdir"]; }
+    fn help(&self) -> &'static str {
+        return "Changes the current directory\n\
+         Usage: cd [path]\n\
+         Relative paths start from the current directory, without a path it prints where we are.";
+    }
+
+    fn completion(&self) -> Option<&'static [&'static str]> { return Some(&["-d"]); }
+
+    fn run(&self, shell: &mut Shell, argv: &[String], _: &[(String, String)], io: &mut Io) -> Result<i32, String> {
+        let path = match argv.get(1) {
+            Some(x) => x,
+            None => {
+                io.stdout.ubwrite(format!("{}\n", shell.dir_path.display()))?;
+                return Ok(0);
+            },
+        };
+        let path = match crate::parse_dir_path(&shell.dir_path, path) {
+            Ok(x) => x,
+            Err(err) => {
+                io.stderr.uswrite("[?ERROR]".red())?;
+                io.stderr.ubwrite(format!(" {}", err))?;
+                return Ok(1);
+            },
+        };
+        if let Err(err) = env::set_current_dir(&path) {
+            io.stderr.uswrite("[sERROR]".red())?;
+            io.stderr.ubwrite(format!(" Failed to switch dir: {}\n", err))?;
+            return Ok(1);
+        }
+        shell.dir_path = path;
+        match shell.dir_path.file_name() {
+            Some(x) => shell.dir_name = x.to_string_lossy().to_string(),
+            None => {
+                io.stderr.uswrite("[ERROR]".red())?;
+                io.stderr.ubwrite(" Rust failed to get directory name separated\n")?;
+            },
+        };
+        return Ok(0);
+    }
+}
+
+struct Clear;
+impl Builtin for Clear {
+    fn name(&self) -> &'static str { return "clear"; }
+    fn aliases(&self) -> &'static [&'static str] { return &["cls"]; }
+    fn help(&self) -> &'static str {
+        return "Clears the terminal\n\
+         Usage: clear";
+    }
+
+    fn completion(&self) -> Option<&'static [&'static str]> { return Some(&[]); }
+
+    fn run(&self, _: &mut Shell, _: &[String], _: &[(String, String)], io: &mut Io) -> Result<i32, String> {
+        io.stdout.clear_term()?;
+        io.stdout.uqueue(cursor::MoveTo(0, 0))?;
+        return Ok(0);
+    }
+}
+
+struct Command;
+impl Builtin for Command {
+    fn name(&self) -> &'static str { return "command"; }
+    fn help(&self) -> &'static str {
+        return "Runs a builtin or program, skipping functions with the same name\n\
+         Usage: command [-v|-V] <name> [args...]\n\
+         -v prints what would run, -V describes it like `type` does.";
+    }
+
+    fn completion(&self) -> Option<&'static [&'static str]> { return Some(&["-o", "-v -V", "-c"]); }
+
+    fn run(&self, shell: &mut Shell, argv: &[String], env_vars: &[(String, String)], io: &mut Io) -> Result<i32, String> {
+        return shell.command_builtin(argv, env_vars, io);
+    }
+}
+
+struct Complete;
+impl Builtin for Complete {
+    fn name(&self) -> &'static str { return "complete"; }
+    fn help(&self) -> &'static str {
+        return "Sets how a command's arguments get completed\n\
+         Usage: complete [-W words] [-o options] [-C command] [-f|-d] [-c] name...\n\
+         \x20      complete -p [name...]\n\
+         \x20      complete -r name...\n\
+         -W completes one of the words, -o the options once a `-` is typed, -C the lines printed by a command,\n\
+         -f and -d files or directories, -c command names. -p prints specs and -r removes them.";
+    }
+
+    fn completion(&self) -> Option<&'static [&'static str]> { return Some(&["-o", "-W -o -f -d -c -C -r -p", "-c"]); }
+
+    fn run(&self, shell: &mut Shell, argv: &[String], _: &[(String, String)], io: &mut Io) -> Result<i32, String> {
+        return shell.complete_builtin(&argv[1..], io);
+    }
+}
+
+struct Debug;
+impl Builtin for Debug {
+    fn name(&self) -> &'static str { return "debug"; }
+    fn help(&self) -> &'static str {
+        return "Shows what the shell sees, for figuring out why something isn't found or doesn't run\n\
+         Usage: debug env|exes|path|state [--json]\n\
+         env is the environment and the variables set for the command, exes every program found in PATH, path\n\
+         each PATH folder and what's wrong with it, state the current directory, git, settings and such.\n\
+         --json prints it as JSON instead, for tools or bug reports.";
+    }
+
+    fn completion(&self) -> Option<&'static [&'static str]> { return Some(&["-W", "env exes path state", "-o", "--json"]); }
+
+    fn run(&self, shell: &mut Shell, argv: &[String], env_vars: &[(String, String)], io: &mut Io) -> Result<i32, String> {
+        return shell.debug_builtin(argv, env_vars, io);
+    }
+}
+
+struct Echo;
+impl Builtin for Echo {
+    fn name(&self) -> &'static str { return "echo"; }
+    fn help(&self) -> &'static str {
+        return "Prints its arguments separated by spaces\n\
+         Usage: echo [-neE] [args...]\n\
+         -n leaves out the newline at the end. -e turns escapes like \\n, \\t, \\e, \\x1b and \\u{1f980} into what\n\
+         they stand for and \\c stops printing right there, -E leaves them alone which is the default.";
+    }
+
+    fn run(&self, _: &mut Shell, argv: &[String], _: &[(String, String)], io: &mut Io) -> Result<i32, String> {
+        let mut newline = true;
+        let mut escapes = false;
+        let mut first = 1;
+        // Only words made of nothing but flags count, `-nope` is something to print
+        while let Some(flags) = argv.get(first).and_then(|x| x.strip_prefix('-')) {
+            if flags.is_empty() || !flags.chars().all(|c| "neE".contains(c)) {
+                break;
+            }
+            for flag in flags.chars() {
+                match flag {
+                    'n' => newline = false,
+                    'e' => escapes = true,
+                    _ => escapes = false,
+                };
+            }
+            first += 1;
+        }
+        let text = argv[first..].join(" ");
+        if escapes {
+            let (bytes, stop) = printf::unescape(&text);
+            io.stdout.ubwrite(bytes)?;
+            if stop {
+                return Ok(0);
+            }
+        } else {
+            io.stdout.ubwrite(text)?;
+        }
+        if newline {
+            io.stdout.ubwrite("\n")?;
+        }
+        return Ok(0);
+    }
+}
+
+struct Exit;
+impl Builtin for Exit {
+    fn name(&self) -> &'static str { return "exit"; }
+    fn aliases(&self) -> &'static [&'static str] { return &["quit", "q", "kys", "kindness"]; }
+    fn help(&self) -> &'static str {
+        return "Leaves the shell\n\
+         Usage: exit [status]\n\
+         The exit status is `status`, wrapped around to 0-255, or the one of the last command when it's left out.\n\
+         A `status` that isn't a number is an error, the shell still exits with a 2.";
+    }
+
+    fn completion(&self) -> Option<&'static [&'static str]> { return Some(&[]); }
+
+    fn run(&self, shell: &mut Shell, argv: &[String], _: &[(String, String)], io: &mut Io) -> Result<i32, String> {
+        let status = match argv.get(1).map(|x| x.trim().parse::<i64>()) {
+            None => shell.last_status,
+            Some(Ok(n)) if argv.len() == 2 => n.rem_euclid(256) as i32,
+            // Still leaves, the rest of a script shouldn't run because of a typo in its `exit`
+            Some(_) => usage_error(io, self.name())?,
+        };
+        shell.should_quit = true;
+        return Ok(status);
+    }
+}
+
+struct Help;
+impl Builtin for Help {
+    fn name(&self) -> &'static str { return "help"; }
+    fn help(&self) -> &'static str {
+        return "Lists the builtins or tells how to use one\n\
+         Usage: help [name...]\n\
+         Without names it lists every builtin with a one line summary. `name --help` works for any builtin too.";
+    }
+
+    fn run(&self, shell: &mut Shell, argv: &[String], _: &[(String, String)], io: &mut Io) -> Result<i32, String> {
+        if argv.len() > 1 {
+            let mut status = 0;
+            for name in argv[1..].iter() {
+                match find(name, shell.name_case()) {
+                    Some(builtin) => write_help(&mut io.stdout, builtin)?,
+                    None => {
+                        io.stderr.uswrite("[?ERROR]".red())?;
+                        io.stderr.ubwrite(format!(" help: No builtin called `{}`\n", name))?;
+                        status = 1;
+                    },
+                };
+            }
+            return Ok(status);
+        }
+        let mut builtins:Vec<&dyn Builtin> = BUILTINS.to_vec();
+        builtins.sort_by_key(|x| x.name());
+        let width = builtins.iter().map(|x| label(*x).len()).max().unwrap_or(0);
+        io.stdout.ubwrite("Builtins, `help <name>` or `<name> --help` tells more about each:\n")?;
+        for builtin in builtins {
+            io.stdout.uswrite(format!("  {:width$}", label(builtin), width = width).bold())?;
+            io.stdout.ubwrite(format!("  {}\n", builtin.help().lines().next().unwrap_or_default()))?;
+        }
+        return Ok(0);
+    }
+}
+
+struct Printf;
+impl Builtin for Printf {
+    fn name(&self) -> &'static str { return "printf"; }
+    fn help(&self) -> &'static str {
+        return "Prints its arguments following a format\n\
+         Usage: printf <format> [args...]\n\
+         %s is a string, %d a whole number, %x one in hex, %f a decimal one, %q a string quoted so xeorvi reads it back\n\
+         as is and %% a plain %. Width and precision go in between like in C: %-10s, %05d, %.2f. The format takes the\n\
+         same escapes as `echo -e` and goes again from the start while there are args left.";
+    }
+
+    fn run(&self, _: &mut Shell, argv: &[String], _: &[(String, String)], io: &mut Io) -> Result<i32, String> {
+        let format = match argv.get(1) {
+            Some(x) => x,
+            None => return usage_error(io, self.name()),
+        };
+        let (out, errors) = printf::printf(format, &argv[2..]);
+        io.stdout.ubwrite(out)?;
+        for err in errors.iter() {
+            io.stderr.uswrite("[?ERROR]".red())?;
+            io.stderr.ubwrite(format!(" printf: {}\n", err))?;
+        }
+        return Ok(if errors.is_empty() { 0 } else { 1 });
+    }
+}
+
+struct Rehash;
+impl Builtin for Rehash {
+    fn name(&self) -> &'static str { return "rehash"; }
+    fn help(&self) -> &'static str {
+        return "Looks through PATH for programs again\n\
+         Usage: rehash\n\
+         Only needed when something got installed somewhere the automatic refresh doesn't notice.";
+    }
+
+    fn completion(&self) -> Option<&'static [&'static str]> { return Some(&[]); }
+
+    fn run(&self, shell: &mut Shell, _: &[String], _: &[(String, String)], _: &mut Io) -> Result<i32, String> {
+        shell.env_exes = exes::ExeIndex::build(shell.name_case());
+        return Ok(0);
+    }
+}
+
+struct Time;
+impl Builtin for Time {
+    fn name(&self) -> &'static str { return "time"; }
+    fn help(&self) -> &'static str {
+        return "Runs a command and reports how long it took\n\
+         Usage: time <command> [args...]\n\
+         Prints the real time, and the user and system CPU time of the programs it ran, to stderr.";
+    }
+
+    fn run(&self, shell: &mut Shell, argv: &[String], env_vars: &[(String, String)], io: &mut Io) -> Result<i32, String> {
+        if argv.len() < 2 {
+            return usage_error(io, self.name());
+        }
+        let usage_before = crate::query_children_cpu_time();
+        let start = std::time::Instant::now();
+        shell.call(argv[1..].to_vec(), env_vars.to_vec())?;
+        let real = start.elapsed();
+        let usage_after = crate::query_children_cpu_time();
+        io.stderr.ubwrite(format!("\nreal\t{}\n", crate::format_time_report(real)))?;
+        if let (Some((user_before, sys_before)), Some((user_after, sys_after))) = (usage_before, usage_after) {
+            io.stderr.ubwrite(format!("user\t{}\n", crate::format_time_report(user_after.saturating_sub(user_before))))?;
+            io.stderr.ubwrite(format!("sys\t{}\n", crate::format_time_report(sys_after.saturating_sub(sys_before))))?;
+        }
+        return Ok(shell.last_status);
+    }
+}
+
+struct Type;
+impl Builtin for Type {
+    fn name(&self) -> &'static str { return "type"; }
+    fn aliases(&self) -> &'static [&'static str] { return &["which"]; }
+    fn help(&self) -> &'static str {
+        return "Tells what a name runs: a function, a builtin or which program\n\
+         Usage: type [-a] <name>...\n\
+         \x20      which [-a] <name>...\n\
+         -a lists everything the name could run instead of just the first. `which` only prints the paths.";
+    }
+
+    fn completion(&self) -> Option<&'static [&'static str]> { return Some(&["-o", "-a", "-c"]); }
+
+    fn run(&self, shell: &mut Shell, argv: &[String], _: &[(String, String)], io: &mut Io) -> Result<i32, String> {
+        return shell.type_builtin(argv, io);
+    }
+}
@@ -0,0 +1,946 @@
+// Tab completion: figuring out which word is being typed and what it could turn into.
+
+use std::collections::HashSet;
+use std::ops::Range;
+use std::{path, process, time};
+
+use crate::builtins::{self, Io};
+use crate::exes::NameCase;
+use crate::lang::{self, Op, TokenKind, WordPart};
+use crate::{Shell, UWrite};
+use crossterm::style::Stylize;
+
+pub struct Candidate {
+    /// The whole word once completed, unquoted
+    pub text: String,
+    /// What goes into the buffer in place of the word, escaped where needed
+    pub replacement: String,
+    pub is_dir: bool,
+    /// Char indices into `text` that matched what was typed
+    pub matched: Vec<usize>,
+    pub score: i64,
+}
+
+/// How often and how recently something got used this session, feeds the ranking
+pub struct Usage {
+    count: u32,
+    last: time::Instant,
+}
+
+pub struct Completion {
+    /// Byte range of the word being completed, an empty range at the end when starting a new word
+    pub range: Range<usize>,
+    pub candidates: Vec<Candidate>,
+}
+
+/// The word at the end of the input and where it sits in the command line
+struct WordContext {
+    range: Range<usize>,
+    word: String,
+    /// The word as typed, quotes and all
+    raw: String,
+    has_vars: bool,
+    /// Whether the word is the program to run rather than one of its arguments
+    is_command: bool,
+    /// The program the word is an argument of
+    command: Option<String>,
+    /// Arguments between the program and the word
+    args: Vec<String>,
+    /// The whole line, handed to spec commands
+    line: String,
+}
+
+fn word_context(shell: &Shell, input: &str) -> WordContext {
+    let tokens = lang::tokenize_partial(input);
+    let (current, previous) = match tokens.split_last() {
+        Some((last, rest)) if last.span.end >= input.len() && matches!(last.kind, TokenKind::Word(_)) => (Some(last), rest),
+        _ => (None, tokens.as_slice()),
+    };
+
+    let mut is_command = true;
+    let mut command = None;
+    let mut args = Vec::new();
+    for tok in previous.iter() {
+        match &tok.kind {
+            TokenKind::Op(Op::Semi | Op::Newline | Op::And | Op::Or | Op::Pipe | Op::Amp | Op::LParen) => {
+                is_command = true;
+                command = None;
+                args.clear();
+            },
+            TokenKind::Op(_) => {},
+            TokenKind::Word(w) => {
+                if !is_command {
+                    args.push(shell.expand_word_joined(w));
+                    continue;
+                }
+                let bare = w.as_bare().unwrap_or_default();
+                // Keywords and assignments leave us looking at where the command goes, same for `time` which runs what follows
+                if (lang::is_keyword(bare) && bare != "in") || bare == "time" {
+                    continue;
+                }
+                if bare.split_once('=').is_some_and(|(name, _)| !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')) {
+                    continue;
+                }
+                is_command = false;
+                command = Some(shell.expand_word_joined(w));
+            },
+        };
+    }
+
+    return match current {
+        Some(tok) => {
+            let (word, has_vars) = match &tok.kind {
+                TokenKind::Word(w) => (shell.expand_word_joined(w), w.parts.iter().any(|p| matches!(p, WordPart::Var { .. }))),
+                TokenKind::Op(_) => unreachable!(),
+            };
+            let raw = input[tok.span.start..].to_string();
+            WordContext { range: tok.span.start..input.len(), word, raw, has_vars, is_command, command, args, line: input.to_string() }
+        },
+        None => WordContext {
+            range: input.len()..input.len(),
+            word: String::new(),
+            raw: String::new(),
+            has_vars: false,
+            is_command,
+            command,
+            args,
+            line: input.to_string(),
+        },
+    };
+}
+
+/// Escapes whatever the tokenizer would otherwise treat specially
+pub fn quote_word(word: &str) -> String {
+    let mut s = String::with_capacity(word.len());
+    for ch in word.chars() {
+        if ch.is_whitespace() || "\\'\"$&|;<>()#`*?[]{}!".contains(ch) {
+            s.push('\\');
+        }
+        s.push(ch);
+    }
+    return s;
+}
+
+/// Files and folders that could complete `word`, relative to the shell's current directory
+fn complete_path(shell: &Shell, ctx: &WordContext, dirs_only: bool, exes_only: bool) -> Vec<Candidate> {
+    let word = ctx.word.as_str();
+    let (dir_part, base) = match word.rfind('/') {
+        Some(idx) => (&word[..idx + 1], &word[idx + 1..]),
+        None => ("", word),
+    };
+    // Joining an absolute path replaces the current directory, so this covers both
+    let search_dir = shell.dir_path.join(dir_part);
+    let entries = match search_dir.read_dir() {
+        Ok(x) => x,
+        Err(_) => return Vec::new(),
+    };
+
+    // Words with variables in them keep what the user typed for the directory part, only the name gets replaced
+    let prefix = if ctx.has_vars {
+        match ctx.raw.rfind('/') {
+            Some(idx) => ctx.raw[..idx + 1].to_string(),
+            None => String::new(),
+        }
+    } else {
+        quote_word(dir_part)
+    };
+
+    let offset = dir_part.chars().count();
+    let mut candidates = Vec::new();
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        // Hidden files only show up when asked for
+        if name.starts_with('.') && !base.starts_with('.') {
+            continue;
+        }
+        let (score, matched) = match fuzzy_match(base, &name) {
+            Some(x) => x,
+            None => continue,
+        };
+        let entry_path = entry.path();
+        let is_dir = entry_path.is_dir();
+        if dirs_only && !is_dir {
+            continue;
+        }
+        if exes_only && !is_dir && !is_executable::is_executable(&entry_path) {
+            continue;
+        }
+        let suffix = if is_dir { "/" } else { "" };
+        candidates.push(Candidate {
+            text: format!("{}{}{}", dir_part, name, suffix),
+            replacement: format!("{}{}{}", prefix, quote_word(&name), suffix),
+            is_dir,
+            matched: matched.into_iter().map(|x| x + offset).collect(),
+            score: score + frecency(shell, &entry_path.to_string_lossy()),
+        });
+    }
+    candidates.sort_by(|a, b| a.text.cmp(&b.text));
+    rank(&mut candidates);
+    return candidates;
+}
+
+fn complete_command(shell: &Shell, ctx: &WordContext) -> Vec<Candidate> {
+    let builtins:Vec<String> = builtins::names().map(|x| x.to_string()).collect();
+    return complete_words(shell, ctx, builtins.iter().chain(shell.env_exes.names()));
+}
+
+fn complete_words<'a>(shell: &Shell, ctx: &WordContext, words: impl Iterator<Item = &'a String>) -> Vec<Candidate> {
+    let mut candidates = Vec::new();
+    // Smart case only makes sense when case means something
+    let pattern = match shell.name_case() {
+        NameCase::Sensitive => ctx.word.clone(),
+        NameCase::Insensitive => ctx.word.to_lowercase(),
+    };
+    // A builtin that's also on PATH like `echo` is still one thing to pick, two of it would never complete
+    let mut seen = HashSet::new();
+    for name in words {
+        if !seen.insert(shell.name_case().key(name)) {
+            continue;
+        }
+        let (score, matched) = match fuzzy_match(&pattern, name) {
+            Some(x) => x,
+            None => continue,
+        };
+        candidates.push(Candidate {
+            text: name.clone(),
+            replacement: quote_word(name),
+            is_dir: false,
+            matched,
+            score: score + frecency(shell, name),
+        });
+    }
+    rank(&mut candidates);
+    return candidates;
+}
+
+/// Works out what the last word of `input` could be completed to
+pub fn complete(shell: &mut Shell, input: &str) -> Completion {
+    let ctx = word_context(shell, input);
+    let candidates = if ctx.is_command {
+        if ctx.word.contains('/') {
+            complete_path(shell, &ctx, false, true)
+        } else {
+            complete_command(shell, &ctx)
+        }
+    } else {
+        complete_args(shell, &ctx)
+    };
+    return Completion { range: ctx.range, candidates };
+}
+
+/// Arguments go through whichever spec fits best, plain files when there's none
+fn complete_args(shell: &mut Shell, ctx: &WordContext) -> Vec<Candidate> {
+    let command = ctx.command.clone().unwrap_or_default();
+    // `git checkout` beats `git`, as long as the user typed the `checkout` part
+    let mut found = None;
+    for used in (0..=ctx.args.len()).rev() {
+        let key = spec_key(&std::iter::once(command.as_str()).chain(ctx.args[..used].iter().map(|x| x.as_str())).collect::<Vec<_>>().join(" "));
+        if let Some(spec) = find_spec(shell, &key) {
+            // Cloned since running its command needs the shell
+            found = Some((spec.clone(), used == ctx.args.len()));
+            break;
+        }
+    }
+    let (spec, right_after) = match found {
+        Some(x) => x,
+        None => return complete_path(shell, ctx, false, false),
+    };
+
+    if ctx.word.starts_with('-') && !spec.options.is_empty() {
+        return complete_words(shell, ctx, spec.options.iter());
+    }
+    if !right_after {
+        return match spec.paths {
+            Some(PathKind::Dirs) => complete_path(shell, ctx, true, false),
+            _ => complete_path(shell, ctx, false, false),
+        };
+    }
+    let mut words = spec.words.clone();
+    if let Some(cmd) = &spec.command {
+        words.extend(run_spec_command(shell, ctx, cmd));
+    }
+    let mut candidates = complete_words(shell, ctx, words.iter());
+    if spec.commands {
+        candidates.extend(complete_command(shell, ctx));
+    }
+    match spec.paths {
+        Some(PathKind::Files) => candidates.extend(complete_path(shell, ctx, false, false)),
+        Some(PathKind::Dirs) => candidates.extend(complete_path(shell, ctx, true, false)),
+        None => {},
+    };
+    rank(&mut candidates);
+    return candidates;
+}
+
+/// How long the command of a spec gets to come up with candidates before it's killed
+const SPEC_COMMAND_BUDGET: time::Duration = time::Duration::from_millis(500);
+
+/// What a spec's `-C` command printed last time, along with what it was run for
+pub struct SpecOutput {
+    command: String,
+    dir: path::PathBuf,
+    /// The line up to the word being completed, typing more of the word doesn't run the command again
+    before: String,
+    words: Vec<String>,
+}
+
+/// Runs the `-C` command of a spec, every line it prints is a candidate. This happens right on the input
+/// loop, so only the budget keeps a slow command from freezing typing. It only runs again once the line
+/// before the word or the directory changes, not on every key.
+fn run_spec_command(shell: &mut Shell, ctx: &WordContext, cmd: &str) -> Vec<String> {
+    let before = &ctx.line[..ctx.range.start];
+    if let Some(last) = &shell.spec_output {
+        if last.command == cmd && last.dir == shell.dir_path && last.before == before {
+            return last.words.clone();
+        }
+    }
+    let argv:Vec<String> = match lang::tokenize(cmd) {
+        Ok(tokens) => tokens.iter().filter_map(|tok| match &tok.kind {
+            TokenKind::Word(w) => Some(shell.expand_word_joined(w)),
+            TokenKind::Op(_) => None,
+        }).collect(),
+        Err(_) => return Vec::new(),
+    };
+    if argv.is_empty() {
+        return Vec::new();
+    }
+    let mut req = process::Command::new(&argv[0]);
+    req.args(&argv[1..]).current_dir(&shell.dir_path).env("COMP_LINE", &ctx.line).env("COMP_WORD", &ctx.word);
+    let words:Vec<String> = match crate::timed_output(&mut req, SPEC_COMMAND_BUDGET) {
+        Ok(buf) => String::from_utf8_lossy(&buf).lines().map(|x| x.trim()).filter(|x| !x.is_empty()).map(String::from).collect(),
+        Err(_) => Vec::new(),
+    };
+    // Failures are kept too, a command that timed out once will time out again
+    shell.spec_output = Some(SpecOutput {
+        command: cmd.to_string(),
+        dir: shell.dir_path.clone(),
+        before: before.to_string(),
+        words: words.clone(),
+    });
+    return words;
+}
+
+/*
+ * ===================================
+ * | Specs
+ * -----------------------------------
+ */
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum PathKind {
+    Files,
+    Dirs,
+}
+
+/// What the arguments of a command complete to, registered with the `complete` builtin
+#[derive(Clone, Default)]
+pub struct Spec {
+    words: Vec<String>,
+    /// Only offered once the word starts with a `-`
+    options: Vec<String>,
+    paths: Option<PathKind>,
+    /// Program names, for things that run another program
+    commands: bool,
+    /// A command line whose output has one candidate per line
+    command: Option<String>,
+}
+
+/// Registered before the rc file runs so it can override or drop any of them. The builtins bring their
+/// own, these are for programs everybody has.
+const BUILTIN_SPECS: &[&[&str]] = &[
+    &[
+        "-W", "add bisect blame branch checkout cherry-pick clone commit diff fetch grep init log merge mv pull push rebase reset restore revert rm show stash status switch tag worktree",
+        "-o", "--version --help --no-pager -C -c",
+        "git",
+    ],
+    &["-C", "git for-each-ref '--format=%(refname:short)' refs/heads refs/tags", "-o", "-b -B --detach --track", "git checkout"],
+    &["-C", "git for-each-ref '--format=%(refname:short)' refs/heads", "-o", "-c -C --detach", "git switch"],
+    &["-C", "git for-each-ref '--format=%(refname:short)' refs/heads", "-o", "-a -d -D -m -r --list --show-current", "git branch"],
+    &["-C", "git for-each-ref '--format=%(refname:short)' refs/heads refs/remotes refs/tags", "-o", "--abort --continue --no-ff --squash", "git merge"],
+    &["-C", "git for-each-ref '--format=%(refname:short)' refs/heads refs/remotes refs/tags", "-o", "-i --abort --continue --skip --onto", "git rebase"],
+    &["-C", "git remote", "-o", "-u --force --force-with-lease --tags --all", "git push"],
+    &["-C", "git remote", "-o", "--rebase --ff-only --all --prune", "git pull", "git fetch"],
+    &["-f", "-o", "-p -u -A --all --patch --force", "git add"],
+    &["-f", "-o", "-p -s --staged --source", "git restore"],
+    &["-f", "-o", "--cached --staged --stat --name-only", "git diff"],
+    &["-f", "-o", "-r --cached --force", "git rm"],
+    &["-o", "-a -m --all --amend --message --no-verify --fixup", "git commit"],
+    &["-W", "list push pop apply drop show clear", "git stash"],
+    &[
+        "-W", "add bench build check clean clippy doc fmt init install metadata new publish remove run search test tree uninstall update",
+        "-o", "--version --list --help --verbose --quiet",
+        "cargo",
+    ],
+    &[
+        "-o", "--release --workspace --all-targets --all-features --features --bin --example --package --target --quiet --verbose",
+        "cargo build", "cargo check", "cargo clippy", "cargo run", "cargo test", "cargo bench", "cargo doc",
+    ],
+];
+
+/// The spec registered for `key`, going by the same case rules as command names
+fn find_spec<'a>(shell: &'a Shell, key: &str) -> Option<&'a Spec> {
+    if let Some(spec) = shell.completions.get(key) {
+        return Some(spec);
+    }
+    let case = shell.name_case();
+    let key = case.key(key);
+    return shell.completions.iter().find(|(x, _)| case.key(x) == key).map(|(_, spec)| spec);
+}
+
+/// Keys are space separated words, `git checkout` is the spec for `checkout`'s arguments
+fn spec_key(name: &str) -> String {
+    return name.split_whitespace().collect::<Vec<_>>().join(" ");
+}
+
+pub fn load_builtin_specs(shell: &mut Shell) {
+    for args in BUILTIN_SPECS.iter() {
+        let args:Vec<String> = args.iter().map(|x| x.to_string()).collect();
+        if let Ok((names, spec)) = parse_spec(&args) {
+            for name in names {
+                shell.completions.insert(name, spec.clone());
+            }
+        }
+    }
+    for builtin in builtins::BUILTINS.iter() {
+        let flags = match builtin.completion() {
+            Some(x) => x,
+            None => continue,
+        };
+        let mut args:Vec<String> = flags.iter().map(|x| x.to_string()).collect();
+        args.push(builtin.name().to_string());
+        args.extend(builtin.aliases().iter().map(|x| x.to_string()));
+        if let Ok((names, spec)) = parse_spec(&args) {
+            for name in names {
+                shell.completions.insert(name, spec.clone());
+            }
+        }
+    }
+    // `help` takes the name of a builtin, whichever ones this build has
+    let words = builtins::names().map(|x| x.to_string()).collect();
+    shell.completions.insert(String::from("help"), Spec { words, ..Spec::default() });
+}
+
+/// Splits `complete`'s arguments into the commands and the spec they get
+fn parse_spec(args: &[String]) -> Result<(Vec<String>, Spec), String> {
+    let mut spec = Spec::default();
+    let mut names = Vec::new();
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        match arg.as_str() {
+            "-W" | "-o" | "-C" => {
+                let value = match it.next() {
+                    Some(x) => x,
+                    None => return Err(format!("{} expects a value", arg)),
+                };
+                match arg.as_str() {
+                    "-W" => spec.words.extend(value.split_whitespace().map(String::from)),
+                    "-o" => spec.options.extend(value.split_whitespace().map(String::from)),
+                    _ => spec.command = Some(value.clone()),
+                };
+            },
+            "-f" => spec.paths = Some(PathKind::Files),
+            "-d" => spec.paths = Some(PathKind::Dirs),
+            "-c" => spec.commands = true,
+            _ if arg.starts_with('-') && arg.len() > 1 => return Err(format!("Unknown flag `{}`", arg)),
+            _ => names.push(spec_key(arg)),
+        };
+    }
+    if names.is_empty() {
+        return Err(String::from("No command to complete was given"));
+    }
+    return Ok((names, spec));
+}
+
+fn quote_list(items: &[String]) -> String {
+    let joined = items.join(" ");
+    return format!("'{}'", joined.replace('\'', "'\\''"));
+}
+
+/// Shows a spec the way it'd be registered
+fn describe_spec(name: &str, spec: &Spec) -> String {
+    let mut s = String::from("complete");
+    if !spec.words.is_empty() {
+        s.push_str(&format!(" -W {}", quote_list(&spec.words)));
+    }
+    if !spec.options.is_empty() {
+        s.push_str(&format!(" -o {}", quote_list(&spec.options)));
+    }
+    if let Some(cmd) = &spec.command {
+        s.push_str(&format!(" -C {}", quote_list(std::slice::from_ref(cmd))));
+    }
+    match spec.paths {
+        Some(PathKind::Files) => s.push_str(" -f"),
+        Some(PathKind::Dirs) => s.push_str(" -d"),
+        None => {},
+    };
+    if spec.commands {
+        s.push_str(" -c");
+    }
+    if name.contains(' ') {
+        s.push_str(&format!(" '{}'", name));
+    } else {
+        s.push_str(&format!(" {}", name));
+    }
+    return s;
+}
+
+impl Shell {
+    /// `complete [-W words] [-o options] [-C command] [-f|-d] [-c] name...` registers how the arguments of `name` complete.
+    /// Words and the command's output are offered right after `name`, options once the word starts with `-`,
+    /// files or folders wherever else. `name` can be `git checkout` to complete a subcommand's arguments.
+    /// `complete -r name...` drops specs, `complete -p [name...]` or no arguments at all lists them.
+    pub fn complete_builtin(&mut self, args: &[String], io: &mut Io) -> Result<i32, String> {
+        let mut status = 0;
+        match args.first().map(|x| x.as_str()) {
+            None | Some("-p") => {
+                let wanted:Vec<String> = args.iter().skip(1).map(|x| spec_key(x)).collect();
+                let mut names:Vec<&String> = self.completions.keys()
+                    .filter(|name| wanted.is_empty() || wanted.contains(name))
+                    .collect();
+                names.sort();
+                for name in names {
+                    io.stdout.ubwrite(format!("{}\n", describe_spec(name, &self.completions[name])))?;
+                }
+                return Ok(0);
+            },
+            Some("-r") => {
+                for name in args.iter().skip(1) {
+                    if self.completions.remove(&spec_key(name)).is_none() {
+                        io.stderr.uswrite("[?ERROR]".red())?;
+                        io.stderr.ubwrite(format!(" complete: No spec for `{}`\n", name))?;
+                        status = 1;
+                    }
+                }
+                return Ok(status);
+            },
+            Some(_) => {},
+        };
+        match parse_spec(args) {
+            Ok((names, spec)) => {
+                for name in names {
+                    self.completions.insert(name, spec.clone());
+                }
+            },
+            Err(err) => {
+                io.stderr.uswrite("[?ERROR]".red())?;
+                io.stderr.ubwrite(format!(" complete: {}\n", err))?;
+                status = builtins::usage_error(io, "complete")?;
+            },
+        };
+        return Ok(status);
+    }
+}
+
+/// The rest of the line fish style: the latest line from history that starts with `input`, or the program being typed
+pub fn autosuggestion(shell: &Shell, input: &str) -> Option<String> {
+    if input.is_empty() {
+        return None;
+    }
+    for line in shell.history.iter().rev() {
+        if line.len() > input.len() && line.starts_with(input) {
+            return Some(line[input.len()..].to_string());
+        }
+    }
+    // Nothing typed before, but the first word can still be finished
+    if input.trim_start().contains(char::is_whitespace) {
+        return None;
+    }
+    let word = input.trim_start();
+    let case = shell.name_case();
+    let names = builtins::names().map(|x| x as &str).chain(shell.env_exes.names().map(|x| x.as_str()));
+    for name in names {
+        if name.len() > word.len() && name.is_char_boundary(word.len()) && case.starts_with(name, word) {
+            return Some(name[word.len()..].to_string());
+        }
+    }
+    return None;
+}
+
+/*
+ * ===================================
+ * | Ranking
+ * -----------------------------------
+ */
+
+const MATCH_SCORE: i64 = 16;
+/// Matching the first letter of a word, like the `c` of `git-checkout`
+const BOUNDARY_BONUS: i64 = 8;
+/// Matching right after the previous typed letter matched
+const CONSECUTIVE_BONUS: i64 = 12;
+/// What was typed is exactly how the name starts, these go before everything else
+const PREFIX_BONUS: i64 = 64;
+
+fn is_word_start(chars: &[char], idx: usize) -> bool {
+    if idx == 0 {
+        return true;
+    }
+    let prev = chars[idx - 1];
+    if "-_./ ".contains(prev) {
+        return true;
+    }
+    return prev.is_lowercase() && chars[idx].is_uppercase();
+}
+
+/// Checks whether `pattern` shows up in `text` in order, not necessarily together, and scores the best way it does.
+/// Gives back the score and the char indices of `text` that got matched. Lowercase patterns ignore case.
+pub fn fuzzy_match(pattern: &str, text: &str) -> Option<(i64, Vec<usize>)> {
+    let pattern:Vec<char> = pattern.chars().collect();
+    let chars:Vec<char> = text.chars().collect();
+    if pattern.is_empty() {
+        return Some((0, Vec::new()));
+    }
+    let ignore_case = !pattern.iter().any(|c| c.is_uppercase());
+    let same = |a: char, b: char| -> bool {
+        if ignore_case {
+            return a.to_lowercase().eq(b.to_lowercase());
+        }
+        return a == b;
+    };
+
+    // Most names don't match at all, find that out before doing the expensive part
+    let mut rest = chars.iter();
+    if !pattern.iter().all(|pch| rest.any(|ch| same(*pch, *ch))) {
+        return None;
+    }
+
+    // best[i][j] is the best score for matching pattern[..=i] with pattern[i] landing on chars[j]
+    let mut best:Vec<Vec<Option<i64>>> = vec![vec![None; chars.len()]; pattern.len()];
+    let mut from:Vec<Vec<usize>> = vec![vec![0; chars.len()]; pattern.len()];
+    for (i, pch) in pattern.iter().enumerate() {
+        // Best `best[i - 1][k] + k` out of the ks that leave a gap before j, skipping a char costs a point
+        let mut gapped:Option<(i64, usize)> = None;
+        for (j, ch) in chars.iter().enumerate() {
+            if i > 0 && j >= 2 {
+                if let Some(prev) = best[i - 1][j - 2] {
+                    let value = prev + (j - 2) as i64;
+                    if gapped.is_none_or(|(x, _)| value > x) {
+                        gapped = Some((value, j - 2));
+                    }
+                }
+            }
+            if !same(*pch, *ch) {
+                continue;
+            }
+            let bonus = MATCH_SCORE + if is_word_start(&chars, j) { BOUNDARY_BONUS } else { 0 };
+            if i == 0 {
+                // Starting further in costs a bit
+                best[i][j] = Some(bonus - j as i64);
+                continue;
+            }
+            if let Some((value, k)) = gapped {
+                best[i][j] = Some(value - (j - 1) as i64 + bonus);
+                from[i][j] = k;
+            }
+            if j > 0 {
+                if let Some(prev) = best[i - 1][j - 1] {
+                    let score = prev + CONSECUTIVE_BONUS + bonus;
+                    if best[i][j].is_none_or(|x| score > x) {
+                        best[i][j] = Some(score);
+                        from[i][j] = j - 1;
+                    }
+                }
+            }
+        }
+    }
+
+    let last = pattern.len() - 1;
+    let (mut j, mut score) = best[last].iter().enumerate()
+        .filter_map(|(j, x)| x.map(|x| (j, x)))
+        .max_by_key(|(_, x)| *x)?;
+    let mut matched = vec![j; pattern.len()];
+    for i in (1..pattern.len()).rev() {
+        j = from[i][j];
+        matched[i - 1] = j;
+    }
+    if matched.iter().enumerate().all(|(i, j)| i == *j) {
+        score += PREFIX_BONUS;
+    }
+    return Some((score, matched));
+}
+
+/// How many single letter inserts, deletes, swaps of neighbours and replacements it takes to turn `a` into `b`
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a:Vec<char> = a.chars().collect();
+    let b:Vec<char> = b.chars().collect();
+    // Three rows are all the table ever needs, the one before the previous is for the swaps
+    let mut before:Vec<usize> = vec![0; b.len() + 1];
+    let mut prev:Vec<usize> = (0..=b.len()).collect();
+    let mut row:Vec<usize> = vec![0; b.len() + 1];
+    for i in 1..=a.len() {
+        row[0] = i;
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            row[j] = (prev[j] + 1).min(row[j - 1] + 1).min(prev[j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                row[j] = row[j].min(before[j - 2] + 1);
+            }
+        }
+        std::mem::swap(&mut before, &mut prev);
+        std::mem::swap(&mut prev, &mut row);
+    }
+    return prev[b.len()];
+}
+
+/// Up to three known commands close enough to `name` to be what the user meant
+pub fn did_you_mean(shell: &Shell, name: &str) -> Vec<String> {
+    // Longer names get to have more typos in them
+    let max_distance = (name.chars().count() / 3).max(1);
+    let case = shell.name_case();
+    let known = builtins::names().map(|x| x.to_string())
+        .chain(shell.functions.keys().cloned())
+        .chain(shell.env_exes.names().cloned());
+    let mut close:Vec<(usize, String)> = Vec::new();
+    for candidate in known {
+        // Getting the case wrong is the closest miss there is, even when case matters
+        let distance = edit_distance(&name.to_lowercase(), &candidate.to_lowercase());
+        // The same name would only show up when the index is out of date, no use suggesting it
+        if case.eq(name, &candidate) || distance > max_distance || close.iter().any(|(_, x)| *x == candidate) {
+            continue;
+        }
+        close.push((distance, candidate));
+    }
+    close.sort_by_key(|(distance, _)| *distance);
+    return close.into_iter().take(3).map(|(_, x)| x).collect();
+}
+
+/// Remembers that `key`, a command name or an absolute path, just got used
+pub fn record_usage(shell: &mut Shell, key: &str) {
+    let usage = shell.usage.entry(key.to_string()).or_insert(Usage { count: 0, last: time::Instant::now() });
+    usage.count += 1;
+    usage.last = time::Instant::now();
+}
+
+/// Bonus for things used often and lately, the more recent the use the more each one counts
+fn frecency(shell: &Shell, key: &str) -> i64 {
+    let usage = match shell.usage.get(key) {
+        Some(x) => x,
+        None => return 0,
+    };
+    let age = usage.last.elapsed().as_secs();
+    let weight = if age < 60 * 60 {
+        4
+    } else if age < 24 * 60 * 60 {
+        2
+    } else {
+        1
+    };
+    return (usage.count as i64 * weight * 4).min(64);
+}
+
+/// Best matches first, ties keep the order they came in
+fn rank(candidates: &mut [Candidate]) {
+    candidates.sort_by_key(|x| std::cmp::Reverse(x.score));
+}
+
+impl Completion {
+    /// Swaps the word in `buf` for the candidate, finished words get a space so the next one can be typed right away
+    pub fn apply(&self, buf: &mut String, idx: usize) {
+        let candidate = match self.candidates.get(idx) {
+            Some(x) => x,
+            None => return,
+        };
+        buf.replace_range(self.range.clone(), &candidate.replacement);
+        // Keep going into folders without having to delete a space first
+        if !candidate.is_dir {
+            buf.push(' ');
+        }
+    }
+
+    /// The longest replacement every candidate starts with, what Tab can fill in without having to pick one
+    pub fn common_prefix(&self) -> String {
+        let mut it = self.candidates.iter();
+        let mut prefix:Vec<char> = match it.next() {
+            Some(x) => x.replacement.chars().collect(),
+            None => return String::new(),
+        };
+        for candidate in it {
+            let same = prefix.iter().zip(candidate.replacement.chars()).take_while(|(a, b)| **a == *b).count();
+            prefix.truncate(same);
+        }
+        // Don't leave half of an escape behind
+        let trailing = prefix.iter().rev().take_while(|ch| **ch == '\\').count();
+        if trailing % 2 == 1 {
+            prefix.pop();
+        }
+        return prefix.into_iter().collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exes::{ExeIndex, NameRule};
+    use std::{env, fs};
+
+    /// A shell whose PATH is a scratch folder with nothing in it but `exes`
+    fn shell_with_exes(test: &str, exes: &[&str]) -> Shell {
+        let dir = env::temp_dir().join(format!("xeorvi-complete-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for exe in exes.iter() {
+            let file = dir.join(exe);
+            fs::write(&file, "").unwrap();
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                fs::set_permissions(&file, fs::Permissions::from_mode(0o755)).unwrap();
+            }
+        }
+        let mut shell = Shell::new("xeorvi", false).unwrap();
+        shell.env_exes = ExeIndex::build_with(Some(dir.into_os_string()), NameRule::Unix, NameCase::Sensitive);
+        return shell;
+    }
+
+    #[test]
+    fn builtin_also_on_path_completes_once() {
+        let mut shell = shell_with_exes("builtin-on-path", &["echo", "ls"]);
+        let completion = complete(&mut shell, "ech");
+        assert_eq!(completion.candidates.iter().map(|x| x.text.as_str()).collect::<Vec<_>>(), vec!["echo"]);
+        let mut buf = String::from("ech");
+        completion.apply(&mut buf, 0);
+        assert_eq!(buf, "echo ");
+    }
+
+    #[test]
+    fn builtins_bring_their_own_specs() {
+        let mut shell = shell_with_exes("builtin-specs", &[]);
+        load_builtin_specs(&mut shell);
+        for builtin in builtins::BUILTINS.iter() {
+            let flags = match builtin.completion() {
+                Some(x) => x,
+                None => continue,
+            };
+            let args:Vec<String> = flags.iter().map(|x| x.to_string()).chain(std::iter::once(builtin.name().to_string())).collect();
+            assert!(parse_spec(&args).is_ok(), "{} has a spec `complete` wouldn't take", builtin.name());
+            for name in std::iter::once(builtin.name()).chain(builtin.aliases().iter().copied()) {
+                assert!(shell.completions.contains_key(name), "{} has no spec", name);
+            }
+        }
+        assert!(complete(&mut shell, "debug ").candidates.iter().any(|x| x.text == "exes"));
+    }
+
+    #[test]
+    fn specs_follow_the_case_policy() {
+        let mut shell = shell_with_exes("spec-case", &[]);
+        load_builtin_specs(&mut shell);
+        shell.vars.insert(String::from("XEORVI_CASE_SENSITIVE"), String::from("no"));
+        assert!(complete(&mut shell, "GIT che").candidates.iter().any(|x| x.text == "checkout"));
+        shell.vars.insert(String::from("XEORVI_CASE_SENSITIVE"), String::from("yes"));
+        assert!(!complete(&mut shell, "GIT che").candidates.iter().any(|x| x.text == "checkout"));
+    }
+
+    #[test]
+    fn fuzzy_matches_in_order() {
+        assert_eq!(fuzzy_match("", "git"), Some((0, Vec::new())));
+        assert_eq!(fuzzy_match("gco", "git-checkout").map(|x| x.1), Some(vec![0, 4, 9]));
+        assert_eq!(fuzzy_match("tg", "git"), None);
+        assert_eq!(fuzzy_match("xyz", "git"), None);
+    }
+
+    #[test]
+    fn fuzzy_smart_case() {
+        assert!(fuzzy_match("gc", "GIT-Checkout").is_some());
+        assert!(fuzzy_match("Gc", "git-checkout").is_none());
+        assert!(fuzzy_match("Gc", "Git-checkout").is_some());
+    }
+
+    #[test]
+    fn fuzzy_scores_prefixes_and_word_starts_higher() {
+        let score = |pattern, text| fuzzy_match(pattern, text).unwrap().0;
+        assert!(score("co", "commit") > score("co", "checkout"));
+        // Skipping ahead to where a word starts is worth more than the closer letter in the middle of one
+        assert!(score("gc", "git-commit") > score("gc", "gxcommit"));
+        assert_eq!(fuzzy_match("fb", "fooBar").map(|x| x.1), Some(vec![0, 3]));
+        assert!(score("chk", "chkconfig") > score("chk", "checkout"));
+    }
+
+    #[test]
+    fn edit_distance_counts_swaps_as_one() {
+        assert_eq!(edit_distance("same", "same"), 0);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("gti", "git"), 1);
+        assert_eq!(edit_distance("ab", "ba"), 1);
+        assert_eq!(edit_distance("ca", "abc"), 3);
+    }
+
+    #[test]
+    fn did_you_mean_finds_typos() {
+        let shell = shell_with_exes("did-you-mean", &["git", "grep", "cargo"]);
+        assert_eq!(did_you_mean(&shell, "gti"), vec!["git"]);
+        assert_eq!(did_you_mean(&shell, "carg"), vec!["cargo"]);
+        assert!(did_you_mean(&shell, "zzzzzz").is_empty());
+    }
+
+    fn completion_of(replacements: &[&str]) -> Completion {
+        let candidates = replacements.iter().map(|x| Candidate {
+            text: x.to_string(),
+            replacement: x.to_string(),
+            is_dir: false,
+            matched: Vec::new(),
+            score: 0,
+        }).collect();
+        return Completion { range: 0..0, candidates };
+    }
+
+    #[test]
+    fn common_prefix_keeps_escapes_whole() {
+        assert_eq!(completion_of(&[]).common_prefix(), "");
+        assert_eq!(completion_of(&["checkout"]).common_prefix(), "checkout");
+        assert_eq!(completion_of(&["checkout", "cherry-pick"]).common_prefix(), "che");
+        assert_eq!(completion_of(&["my\\ file", "my\\ folder"]).common_prefix(), "my\\ f");
+        assert_eq!(completion_of(&["a\\ b", "a\\(c"]).common_prefix(), "a");
+        assert_eq!(completion_of(&["a\\\\x", "a\\\\y"]).common_prefix(), "a\\\\");
+    }
+
+    #[test]
+    fn quote_word_escapes_what_the_tokenizer_would_eat() {
+        assert_eq!(quote_word("plain-name.rs"), "plain-name.rs");
+        assert_eq!(quote_word("my file"), "my\\ file");
+        assert_eq!(quote_word("$HOME"), "\\$HOME");
+        assert_eq!(quote_word("it's \"quoted\""), "it\\'s\\ \\\"quoted\\\"");
+        assert_eq!(quote_word("a\\b"), "a\\\\b");
+        assert_eq!(quote_word("x(1)*[2]"), "x\\(1\\)\\*\\[2\\]");
+        assert_eq!(quote_word("ünïcode"), "ünïcode");
+    }
+
+    #[test]
+    fn frecency_favors_recent_use() {
+        let mut shell = shell_with_exes("frecency", &[]);
+        assert_eq!(frecency(&shell, "make"), 0);
+        record_usage(&mut shell, "make");
+        assert_eq!(frecency(&shell, "make"), 16);
+        record_usage(&mut shell, "make");
+        assert_eq!(frecency(&shell, "make"), 32);
+
+        let hours_ago = |hours: u64| time::Instant::now().checked_sub(time::Duration::from_secs(hours * 60 * 60)).unwrap();
+        shell.usage.insert(String::from("cmake"), Usage { count: 2, last: hours_ago(2) });
+        assert_eq!(frecency(&shell, "cmake"), 16);
+        shell.usage.insert(String::from("cmake"), Usage { count: 2, last: hours_ago(48) });
+        assert_eq!(frecency(&shell, "cmake"), 8);
+        // Using something a lot only gets it so far
+        shell.usage.insert(String::from("cmake"), Usage { count: 1000, last: time::Instant::now() });
+        assert_eq!(frecency(&shell, "cmake"), 64);
+    }
+
+    #[test]
+    fn rank_is_stable() {
+        let mut completion = completion_of(&["a", "b", "c", "d"]);
+        for (candidate, score) in completion.candidates.iter_mut().zip([1, 5, 1, 5]) {
+            candidate.score = score;
+        }
+        rank(&mut completion.candidates);
+        assert_eq!(completion.candidates.iter().map(|x| x.text.as_str()).collect::<Vec<_>>(), vec!["b", "d", "a", "c"]);
+    }
+
+    #[test]
+    fn used_commands_rank_first() {
+        let mut shell = shell_with_exes("frecency-rank", &["zgrep-thing", "zgrip-thing"]);
+        let texts = |shell: &mut Shell| complete(shell, "zgr").candidates.into_iter().map(|x| x.text).collect::<Vec<_>>();
+        record_usage(&mut shell, "zgrip-thing");
+        assert_eq!(texts(&mut shell), vec!["zgrip-thing", "zgrep-thing"]);
+        record_usage(&mut shell, "zgrep-thing");
+        record_usage(&mut shell, "zgrep-thing");
+        assert_eq!(texts(&mut shell), vec!["zgrep-thing", "zgrip-thing"]);
+    }
+}
@@ -0,0 +1,299 @@
+// `debug`: a look at what the shell sees, for when PATH or the environment is acting up on somebody else's
+// machine. Every subcommand builds a `Value` that prints as an outline for people or as JSON for tools.
+
+use std::{env, ffi};
+
+use is_executable::IsExecutable;
+
+use crate::builtins::{self, Io};
+use crate::exes::{NameCase, NameRule};
+use crate::{Shell, UWrite};
+
+pub enum Value {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    List(Vec<Value>),
+    /// Keys stay in the order they were added in
+    Map(Vec<(String, Value)>),
+}
+
+fn text(s: impl std::fmt::Display) -> Value {
+    return Value::Str(s.to_string());
+}
+
+fn opt_text(s: Option<impl std::fmt::Display>) -> Value {
+    return match s {
+        Some(x) => text(x),
+        None => Value::Null,
+    };
+}
+
+fn json_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for ch in s.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        };
+    }
+    out.push('"');
+    return out;
+}
+
+impl Value {
+    fn scalar(&self) -> Option<String> {
+        return match self {
+            Value::Null => Some(String::from("null")),
+            Value::Bool(x) => Some(x.to_string()),
+            Value::Int(x) => Some(x.to_string()),
+            // JSON has no infinity or NaN
+            Value::Float(x) if x.is_finite() => Some(x.to_string()),
+            Value::Float(_) => Some(String::from("null")),
+            Value::Str(x) => Some(json_escape(x)),
+            Value::List(_) | Value::Map(_) => None,
+        };
+    }
+
+    pub fn to_json(&self, indent: usize) -> String {
+        if let Some(x) = self.scalar() {
+            return x;
+        }
+        let pad = "  ".repeat(indent + 1);
+        let (open, close, items):(_, _, Vec<String>) = match self {
+            Value::List(items) => ('[', ']', items.iter().map(|x| format!("{}{}", pad, x.to_json(indent + 1))).collect()),
+            Value::Map(items) => ('{', '}', items.iter().map(|(k, v)| format!("{}{}: {}", pad, json_escape(k), v.to_json(indent + 1))).collect()),
+            _ => unreachable!(),
+        };
+        if items.is_empty() {
+            return format!("{}{}", open, close);
+        }
+        return format!("{}\n{}\n{}{}", open, items.join(",\n"), "  ".repeat(indent), close);
+    }
+
+    /// `key: value` lines, nested things go underneath indented. Nulls are left out, nobody reads those.
+    pub fn to_outline(&self, indent: usize) -> String {
+        let pad = "  ".repeat(indent);
+        let mut out = String::new();
+        match self {
+            Value::Map(items) => {
+                for (k, v) in items.iter() {
+                    match v {
+                        Value::Null => {},
+                        Value::Str(x) => out.push_str(&format!("{}{}: {}\n", pad, k, x)),
+                        Value::List(x) if x.is_empty() => out.push_str(&format!("{}{}: none\n", pad, k)),
+                        Value::Map(x) if x.is_empty() => out.push_str(&format!("{}{}: none\n", pad, k)),
+                        Value::List(_) | Value::Map(_) => out.push_str(&format!("{}{}:\n{}", pad, k, v.to_outline(indent + 1))),
+                        _ => out.push_str(&format!("{}{}: {}\n", pad, k, v.scalar().unwrap_or_default())),
+                    };
+                }
+            },
+            Value::List(items) => {
+                for item in items.iter() {
+                    match item {
+                        Value::Str(x) => out.push_str(&format!("{}- {}\n", pad, x)),
+                        Value::List(_) | Value::Map(_) => {
+                            // The first line gets the dash, the rest line up after it
+                            let nested = item.to_outline(indent + 1);
+                            out.push_str(&format!("{}- {}", pad, nested.trim_start()));
+                        },
+                        _ => out.push_str(&format!("{}- {}\n", pad, item.scalar().unwrap_or_default())),
+                    };
+                }
+            },
+            _ => out.push_str(&format!("{}{}\n", pad, self.scalar().unwrap_or_default())),
+        };
+        return out;
+    }
+}
+
+/// Settings read from variables, along with what's used when they're not set
+const SETTINGS: [(&str, &str); 6] = [
+    ("XEORVI_RC", "~/.xeorvirc"),
+    ("XEORVI_PROMPT_TOP", "built in"),
+    ("XEORVI_PROMPT", "built in"),
+    ("XEORVI_GIT_BUDGET", "0.2"),
+    ("XEORVI_DURATION_THRESHOLD", "2.0"),
+    ("XEORVI_CASE_SENSITIVE", "depends on the platform"),
+];
+
+fn env_map(vars: impl Iterator<Item = (ffi::OsString, ffi::OsString)>) -> Value {
+    let mut vars:Vec<(String, Value)> = vars.map(|(k, v)| (k.to_string_lossy().to_string(), text(v.to_string_lossy()))).collect();
+    vars.sort_by(|a, b| a.0.cmp(&b.0));
+    return Value::Map(vars);
+}
+
+impl Shell {
+    fn debug_env(&self, env_vars: &[(String, String)]) -> Value {
+        let mut vars:Vec<(String, String)> = self.vars.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+        vars.sort();
+        return Value::Map(vec![
+            (String::from("command"), Value::Map(env_vars.iter().map(|(k, v)| (k.clone(), text(v))).collect())),
+            (String::from("environment"), env_map(env::vars_os())),
+            // Set with `NAME=value`, programs don't get these
+            (String::from("shell"), Value::Map(vars.into_iter().map(|(k, v)| (k, text(v))).collect())),
+        ]);
+    }
+
+    fn debug_exes(&self) -> Value {
+        let mut exes = Vec::new();
+        for (name, exe) in self.env_exes.iter() {
+            exes.push(Value::Map(vec![
+                (String::from("name"), text(name)),
+                (String::from("path"), text(exe.path.display())),
+                (String::from("target"), opt_text(exe.target.as_ref().map(|x| x.display()))),
+            ]));
+        }
+        return Value::Map(vec![
+            (String::from("count"), Value::Int(self.env_exes.len() as i64)),
+            (String::from("exes"), Value::List(exes)),
+        ]);
+    }
+
+    fn debug_path(&self, env_vars: &[(String, String)]) -> Value {
+        let path_var = match self.command_path_var(env_vars) {
+            Some(x) => x,
+            None => return Value::Map(vec![(String::from("path"), Value::Null)]),
+        };
+        let mut dirs = Vec::new();
+        for dir in env::split_paths(&path_var) {
+            let mut info = vec![
+                (String::from("dir"), text(dir.display())),
+                (String::from("exists"), Value::Bool(dir.exists())),
+                (String::from("folder"), Value::Bool(dir.is_dir())),
+            ];
+            if dir.as_os_str().is_empty() {
+                // Means the current directory to some programs and nothing to others, worth pointing out
+                info.push((String::from("problem"), text("empty entry")));
+            }
+            if dir.is_dir() {
+                match dir.read_dir() {
+                    Ok(entries) => {
+                        let (mut files, mut exes, mut shadowed) = (0, 0, Vec::new());
+                        for entry in entries.flatten() {
+                            let path = entry.path();
+                            if !path.is_file() {
+                                continue;
+                            }
+                            files += 1;
+                            if !path.is_executable() {
+                                continue;
+                            }
+                            exes += 1;
+                            // Found earlier in PATH, this one never runs
+                            let name = entry.file_name().to_string_lossy().to_string();
+                            if let Some(exe) = self.env_exes.get(&name) {
+                                if exe.path.parent() != Some(dir.as_path()) {
+                                    shadowed.push(text(format!("{} by {}", name, exe.path.display())));
+                                }
+                            }
+                        }
+                        info.push((String::from("readable"), Value::Bool(true)));
+                        info.push((String::from("files"), Value::Int(files)));
+                        info.push((String::from("executables"), Value::Int(exes)));
+                        info.push((String::from("shadowed"), Value::List(shadowed)));
+                    },
+                    Err(err) => {
+                        info.push((String::from("readable"), Value::Bool(false)));
+                        info.push((String::from("problem"), text(err)));
+                    },
+                };
+            }
+            dirs.push(Value::Map(info));
+        }
+        return Value::Map(vec![
+            (String::from("path"), text(path_var.to_string_lossy())),
+            (String::from("dirs"), Value::List(dirs)),
+        ]);
+    }
+
+    fn debug_state(&self) -> Value {
+        // Only the prompt keeps this up to date, scripts have to ask
+        let git_status = match &self.git_status {
+            Some(x) => Some(x.clone()),
+            None => self.query_git_status(),
+        };
+        let git = match &git_status {
+            None => Value::Null,
+            Some(git) => Value::Map(vec![
+                (String::from("summary"), text(git.summary())),
+                (String::from("head"), text(&git.head)),
+                (String::from("detached"), Value::Bool(git.detached)),
+                (String::from("commit"), opt_text(git.oid.as_ref())),
+                (String::from("staged"), Value::Int(git.staged as i64)),
+                (String::from("unstaged"), Value::Int(git.unstaged as i64)),
+                (String::from("untracked"), Value::Int(git.untracked as i64)),
+                (String::from("conflicted"), Value::Int(git.conflicted as i64)),
+                (String::from("ahead"), Value::Int(git.ahead as i64)),
+                (String::from("behind"), Value::Int(git.behind as i64)),
+                (String::from("stashes"), Value::Int(git.stashes as i64)),
+                (String::from("operation"), opt_text(git.operation)),
+                (String::from("timed_out"), Value::Bool(git.timed_out)),
+            ]),
+        };
+        let config = SETTINGS.iter().map(|(name, default)| {
+            let value = match self.lookup_var(name) {
+                Some(x) => text(x),
+                None => text(format!("unset, {}", default)),
+            };
+            return (name.to_string(), value);
+        }).collect();
+        let rule = match self.env_exes.rule() {
+            NameRule::Unix => text("unix, anything with the executable bit"),
+            NameRule::Windows(exts) => text(format!("windows, {}", exts.join(" "))),
+        };
+        let case = match self.name_case() {
+            NameCase::Sensitive => "sensitive",
+            NameCase::Insensitive => "insensitive",
+        };
+        let mut functions:Vec<&String> = self.functions.keys().collect();
+        functions.sort();
+        return Value::Map(vec![
+            (String::from("version"), text(env!("CARGO_PKG_VERSION"))),
+            (String::from("debug_build"), Value::Bool(cfg!(debug_assertions))),
+            (String::from("program"), text(&self.program_name)),
+            (String::from("interactive"), Value::Bool(self.interactive)),
+            (String::from("cwd"), text(self.dir_path.display())),
+            (String::from("process_cwd"), opt_text(env::current_dir().ok().map(|x| x.display().to_string()))),
+            (String::from("script"), text(&self.script_name)),
+            (String::from("args"), Value::List(self.positional.iter().map(text).collect())),
+            (String::from("last_status"), Value::Int(self.last_status as i64)),
+            (String::from("last_duration"), self.last_duration.map(|x| Value::Float(x.as_secs_f64())).unwrap_or(Value::Null)),
+            (String::from("git"), git),
+            (String::from("config"), Value::Map(config)),
+            (String::from("name_case"), text(case)),
+            (String::from("exe_rule"), rule),
+            (String::from("exes"), Value::Int(self.env_exes.len() as i64)),
+            (String::from("builtins"), Value::List(builtins::names().map(text).collect())),
+            (String::from("functions"), Value::List(functions.into_iter().map(text).collect())),
+            (String::from("completions"), Value::Int(self.completions.len() as i64)),
+            (String::from("history"), Value::Int(self.history.len() as i64)),
+        ]);
+    }
+
+    /// `debug env|exes|path|state [--json]`
+    pub fn debug_builtin(&mut self, argv: &[String], env_vars: &[(String, String)], io: &mut Io) -> Result<i32, String> {
+        let json = argv.iter().skip(2).any(|x| x == "--json");
+        let value = match argv.get(1).map(|x| x.as_str()) {
+            Some("env") => self.debug_env(env_vars),
+            Some("exes") => self.debug_exes(),
+            Some("path") => self.debug_path(env_vars),
+            Some("state") => self.debug_state(),
+            _ => return builtins::usage_error(io, "debug"),
+        };
+        if json {
+            io.stdout.ubwrite(format!("{}\n", value.to_json(0)))?;
+        } else {
+            io.stdout.ubwrite(value.to_outline(0))?;
+        }
+        return Ok(0);
+    }
+}
@@ -0,0 +1,483 @@
+// The programs reachable through PATH, indexed by name and kept up to date without rescanning on every keystroke.
+
+use std::collections::HashMap;
+use std::{env, ffi, path, time};
+
+use is_executable::IsExecutable;
+
+#[derive(Clone)]
+pub struct Exe {
+    /// Where it was found in PATH
+    pub path: path::PathBuf,
+    /// What `path` ends up pointing to when it's a symlink
+    pub target: Option<path::PathBuf>,
+}
+
+pub struct ExeIndex {
+    rule: NameRule,
+    case: NameCase,
+    /// Where each name lives, keyed by `NameCase::key`. The first PATH directory that has it wins
+    by_name: HashMap<String, Exe>,
+    /// Names in PATH order, which is the order suggestions list them in
+    names: Vec<String>,
+    /// PATH as it was when the index got built
+    path_var: Option<ffi::OsString>,
+    /// Every PATH entry with its modification time back then, adding or removing a file bumps it
+    stamps: Vec<(path::PathBuf, Option<time::SystemTime>)>,
+}
+
+fn query_mtime(path: &path::Path) -> Option<time::SystemTime> {
+    return path.metadata().and_then(|x| x.modified()).ok();
+}
+
+/// Most systems give up after 40 links too
+const MAX_LINK_DEPTH: usize = 40;
+
+/// Follows a chain of symlinks to the file at the end of it, None when it's broken or goes in circles
+pub fn resolve_link(path: &path::Path) -> Option<path::PathBuf> {
+    let mut current = path.to_path_buf();
+    let mut seen = vec![current.clone()];
+    while current.is_symlink() {
+        let linked = current.read_link().ok()?;
+        // Relative targets are relative to the folder the link is in
+        current = match current.parent() {
+            Some(parent) => parent.join(linked),
+            None => linked,
+        };
+        if seen.contains(&current) || seen.len() > MAX_LINK_DEPTH {
+            return None;
+        }
+        seen.push(current.clone());
+    }
+    // Gets rid of the `..`s relative links leave behind
+    return Some(current.canonicalize().unwrap_or(current));
+}
+
+/// Whether `Git` and `git` are the same command
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NameCase {
+    Sensitive,
+    Insensitive,
+}
+
+impl NameCase {
+    /// What the OS does with file names, Windows doesn't care about case and everyone else does
+    pub fn platform() -> Self {
+        if cfg!(windows) {
+            return NameCase::Insensitive;
+        }
+        return NameCase::Sensitive;
+    }
+
+    /// Reads a yes/no setting of whether names are case sensitive, None when it's neither
+    pub fn from_setting(value: &str) -> Option<Self> {
+        return match value.trim().to_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => Some(NameCase::Sensitive),
+            "0" | "false" | "no" | "off" => Some(NameCase::Insensitive),
+            _ => None,
+        };
+    }
+
+    /// What names get looked up by, two names with the same key are the same command
+    pub fn key(&self, name: &str) -> String {
+        return match self {
+            NameCase::Sensitive => name.to_string(),
+            NameCase::Insensitive => name.to_lowercase(),
+        };
+    }
+
+    pub fn eq(&self, a: &str, b: &str) -> bool {
+        return match self {
+            NameCase::Sensitive => a == b,
+            NameCase::Insensitive => a.to_lowercase() == b.to_lowercase(),
+        };
+    }
+
+    pub fn starts_with(&self, name: &str, prefix: &str) -> bool {
+        return match self {
+            NameCase::Sensitive => name.starts_with(prefix),
+            NameCase::Insensitive => name.to_lowercase().starts_with(&prefix.to_lowercase()),
+        };
+    }
+}
+
+/// Used when `PATHEXT` isn't set, what cmd.exe assumes too
+const DEFAULT_PATHEXT: &str = ".COM;.EXE;.BAT;.CMD";
+
+/// How file names turn into command names
+#[derive(Debug, Clone, PartialEq)]
+pub enum NameRule {
+    /// Any file with the executable bit, called by its whole name
+    Unix,
+    /// Files ending in one of these extensions (lowercase, in `PATHEXT` order), called without it
+    Windows(Vec<String>),
+}
+
+impl NameRule {
+    pub fn from_env() -> Self {
+        if cfg!(windows) {
+            return NameRule::from_pathext(&env::var("PATHEXT").unwrap_or(DEFAULT_PATHEXT.to_string()));
+        }
+        return NameRule::Unix;
+    }
+
+    pub fn from_pathext(pathext: &str) -> Self {
+        let mut exts = Vec::new();
+        for ext in pathext.split(';') {
+            let ext = ext.trim().to_lowercase();
+            if ext.is_empty() {
+                continue;
+            }
+            let ext = if ext.starts_with('.') { ext } else { format!(".{}", ext) };
+            if !exts.contains(&ext) {
+                exts.push(ext);
+            }
+        }
+        return NameRule::Windows(exts);
+    }
+
+    /// The name a file gets called by and how much its extension counts, lower wins within the same folder
+    fn name_of(&self, file_name: &str) -> Option<(String, usize)> {
+        let exts = match self {
+            NameRule::Unix => return Some((file_name.to_string(), 0)),
+            NameRule::Windows(exts) => exts,
+        };
+        let lower = file_name.to_lowercase();
+        for (rank, ext) in exts.iter().enumerate() {
+            if lower.len() > ext.len() && lower.ends_with(ext.as_str()) {
+                return Some((file_name[..file_name.len() - ext.len()].to_string(), rank));
+            }
+        }
+        return None;
+    }
+
+    /// Files in `dir` that `name` could mean, best first
+    fn candidates(&self, dir: &path::Path, name: &str) -> Vec<path::PathBuf> {
+        let exts = match self {
+            NameRule::Unix => return vec![dir.join(name)],
+            NameRule::Windows(exts) => exts,
+        };
+        let mut candidates = Vec::new();
+        // Typing out the extension picks that file
+        if self.name_of(name).is_some() {
+            candidates.push(dir.join(name));
+        }
+        for ext in exts.iter() {
+            candidates.push(dir.join(format!("{}{}", name, ext)));
+        }
+        return candidates;
+    }
+}
+
+/// Checks whether `path` is something that can run, gives back what it's called and its rank from `NameRule::name_of`
+fn probe(path: path::PathBuf, rule: &NameRule) -> Option<(String, usize, Exe)> {
+    // Links are named after themselves but only count when whatever they point to can run
+    let target = if path.is_symlink() {
+        Some(resolve_link(&path)?)
+    } else {
+        None
+    };
+    let resolved = target.as_deref().unwrap_or(&path);
+    if !resolved.is_file() {
+        return None;
+    }
+    // Windows goes by the extension alone, there's no executable bit to look at
+    if *rule == NameRule::Unix && !resolved.is_executable() {
+        return None;
+    }
+    let (name, rank) = rule.name_of(path.file_name()?.to_str()?)?;
+    return Some((name, rank, Exe { path, target }));
+}
+
+/// Finds the file `name` runs by walking `path_var` the way the OS would, no index involved so it's never stale
+pub fn resolve(path_var: &ffi::OsStr, name: &str, rule: &NameRule) -> Option<Exe> {
+    for dir in env::split_paths(path_var) {
+        for candidate in rule.candidates(&dir, name) {
+            if let Some((_, _, exe)) = probe(candidate, rule) {
+                return Some(exe);
+            }
+        }
+    }
+    return None;
+}
+
+/// The program to start for a file and the arguments that go before the user's, scripts need an interpreter
+pub fn spawn_parts(path: &path::Path) -> (ffi::OsString, Vec<ffi::OsString>) {
+    let ext = path.extension().map(|x| x.to_string_lossy().to_lowercase()).unwrap_or_default();
+    return match ext.as_str() {
+        "bat" | "cmd" => ("cmd".into(), vec!["/c".into(), path.into()]),
+        "ps1" => ("powershell".into(), vec!["-NoProfile".into(), "-ExecutionPolicy".into(), "Bypass".into(), "-File".into(), path.into()]),
+        _ => (path.into(), Vec::new()),
+    };
+}
+
+impl ExeIndex {
+    pub fn build(case: NameCase) -> Self {
+        return ExeIndex::build_with(env::var_os("PATH"), NameRule::from_env(), case);
+    }
+
+    pub fn build_with(path_var: Option<ffi::OsString>, rule: NameRule, case: NameCase) -> Self {
+        let mut index = ExeIndex {
+            rule,
+            case,
+            by_name: HashMap::new(),
+            names: Vec::new(),
+            path_var,
+            stamps: Vec::new(),
+        };
+        let path_var = match &index.path_var {
+            Some(x) => x.clone(),
+            None => return index,
+        };
+        for dir in env::split_paths(&path_var) {
+            index.stamps.push((dir.clone(), query_mtime(&dir)));
+            let mut found = Vec::new();
+            // Somebody out there has a file straight in their PATH, might as well take it
+            if dir.is_file() {
+                found.extend(probe(dir, &index.rule));
+            } else if let Ok(entries) = dir.read_dir() {
+                found.extend(entries.flatten().filter_map(|entry| probe(entry.path(), &index.rule)));
+            }
+            // Folders come in PATH order, inside of one the extension order decides: foo.com beats foo.exe
+            found.sort_by_key(|(_, rank, _)| *rank);
+            for (name, _, exe) in found {
+                index.add(name, exe);
+            }
+        }
+        return index;
+    }
+
+    fn add(&mut self, name: String, exe: Exe) {
+        let key = self.case.key(&name);
+        if self.by_name.contains_key(&key) {
+            return;
+        }
+        self.names.push(name);
+        self.by_name.insert(key, exe);
+    }
+
+    pub fn rule(&self) -> &NameRule {
+        return &self.rule;
+    }
+
+    pub fn case(&self) -> NameCase {
+        return self.case;
+    }
+
+    /// Whether PATH or the case policy changed, or one of PATH's directories got something installed or
+    /// removed since the last build
+    pub fn is_stale(&self, case: NameCase) -> bool {
+        if env::var_os("PATH") != self.path_var || NameRule::from_env() != self.rule || case != self.case {
+            return true;
+        }
+        return self.stamps.iter().any(|(dir, mtime)| query_mtime(dir) != *mtime);
+    }
+
+    /// Rebuilds the index if it's stale, returns whether it did
+    pub fn refresh(&mut self, case: NameCase) -> bool {
+        if !self.is_stale(case) {
+            return false;
+        }
+        *self = ExeIndex::build(case);
+        return true;
+    }
+
+    /// What running `name` would start
+    pub fn get(&self, name: &str) -> Option<&Exe> {
+        return self.by_name.get(&self.case.key(name));
+    }
+
+    /// Every program called `name` in PATH, not only the one that wins, in PATH order
+    pub fn find_all(&self, name: &str) -> Vec<Exe> {
+        let mut found = Vec::new();
+        for (dir, _) in self.stamps.iter() {
+            if dir.is_file() {
+                if let Some((found_name, _, exe)) = probe(dir.clone(), &self.rule) {
+                    if self.case.eq(&found_name, name) {
+                        found.push(exe);
+                    }
+                }
+                continue;
+            }
+            // The file system might care about case even when we don't, only looking at everything is sure to find them all
+            if self.case == NameCase::Insensitive {
+                let entries = match dir.read_dir() {
+                    Ok(x) => x,
+                    Err(_) => continue,
+                };
+                let mut matching:Vec<_> = entries.flatten()
+                    .filter_map(|entry| probe(entry.path(), &self.rule))
+                    .filter(|(found_name, _, _)| self.case.eq(found_name, name))
+                    .collect();
+                matching.sort_by_key(|(_, rank, _)| *rank);
+                found.extend(matching.into_iter().map(|(_, _, exe)| exe));
+                continue;
+            }
+            for candidate in self.rule.candidates(dir, name) {
+                if let Some((_, _, exe)) = probe(candidate, &self.rule) {
+                    found.push(exe);
+                }
+            }
+        }
+        return found;
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        return self.by_name.contains_key(&self.case.key(name));
+    }
+
+    /// Names in PATH order
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        return self.names.iter();
+    }
+
+    /// Names along with where they live, in PATH order
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Exe)> {
+        return self.names.iter().map(|name| (name, &self.by_name[&self.case.key(name)]));
+    }
+
+    pub fn len(&self) -> usize {
+        return self.names.len();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// A scratch folder with a fake PATH made of `dirs`, each one with the files listed
+    struct FakePath {
+        root: path::PathBuf,
+        path_var: ffi::OsString,
+    }
+
+    impl FakePath {
+        fn new(test: &str, dirs: &[(&str, &[&str])]) -> Self {
+            let root = env::temp_dir().join(format!("xeorvi-exes-{}-{}", test, std::process::id()));
+            let _ = fs::remove_dir_all(&root);
+            let mut paths = Vec::new();
+            for (dir, files) in dirs.iter() {
+                let dir = root.join(dir);
+                fs::create_dir_all(&dir).unwrap();
+                for file in files.iter() {
+                    fs::write(dir.join(file), "").unwrap();
+                }
+                paths.push(dir);
+            }
+            let path_var = env::join_paths(paths).unwrap();
+            return FakePath { root, path_var };
+        }
+
+        fn file(&self, dir: &str, file: &str) -> path::PathBuf {
+            return self.root.join(dir).join(file);
+        }
+    }
+
+    impl Drop for FakePath {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    fn windows() -> NameRule {
+        return NameRule::from_pathext(".COM;.EXE;.BAT;.CMD;.PS1");
+    }
+
+    #[test]
+    fn pathext_is_normalized() {
+        assert_eq!(NameRule::from_pathext(".COM; exe;;.Bat;.com"), NameRule::Windows(vec![".com".into(), ".exe".into(), ".bat".into()]));
+    }
+
+    #[test]
+    fn names_drop_the_extension() {
+        let rule = windows();
+        assert_eq!(rule.name_of("cargo.exe"), Some(("cargo".into(), 1)));
+        assert_eq!(rule.name_of("Build.CMD"), Some(("Build".into(), 3)));
+        assert_eq!(rule.name_of("notes.txt"), None);
+        assert_eq!(rule.name_of(".exe"), None);
+        assert_eq!(NameRule::Unix.name_of("cargo.exe"), Some(("cargo.exe".into(), 0)));
+    }
+
+    #[test]
+    fn index_only_takes_pathext_files() {
+        let fake = FakePath::new("index", &[("bin", &["tool.exe", "setup.ps1", "readme.txt", "deploy.cmd"])]);
+        let index = ExeIndex::build_with(Some(fake.path_var.clone()), windows(), NameCase::Insensitive);
+        let mut names:Vec<_> = index.names().cloned().collect();
+        names.sort();
+        assert_eq!(names, vec!["deploy", "setup", "tool"]);
+    }
+
+    #[test]
+    fn extension_order_wins_within_a_folder() {
+        let fake = FakePath::new("ext-order", &[("bin", &["foo.exe", "foo.com", "foo.bat"])]);
+        let index = ExeIndex::build_with(Some(fake.path_var.clone()), windows(), NameCase::Insensitive);
+        assert_eq!(index.get("foo").map(|x| x.path.clone()), Some(fake.file("bin", "foo.com")));
+        let resolved = resolve(&fake.path_var, "foo", &windows()).map(|x| x.path);
+        assert_eq!(resolved, Some(fake.file("bin", "foo.com")));
+    }
+
+    #[test]
+    fn path_order_beats_extension_order() {
+        let fake = FakePath::new("path-order", &[("first", &["foo.cmd"]), ("second", &["foo.com", "bar.exe"])]);
+        let index = ExeIndex::build_with(Some(fake.path_var.clone()), windows(), NameCase::Insensitive);
+        assert_eq!(index.get("foo").map(|x| x.path.clone()), Some(fake.file("first", "foo.cmd")));
+        assert_eq!(index.get("bar").map(|x| x.path.clone()), Some(fake.file("second", "bar.exe")));
+        assert_eq!(resolve(&fake.path_var, "foo", &windows()).map(|x| x.path), Some(fake.file("first", "foo.cmd")));
+
+        let all:Vec<_> = index.find_all("foo").into_iter().map(|x| x.path).collect();
+        assert_eq!(all, vec![fake.file("first", "foo.cmd"), fake.file("second", "foo.com")]);
+    }
+
+    #[test]
+    fn case_policy_decides_what_counts_as_the_same_name() {
+        let fake = FakePath::new("case", &[("first", &["Tool.exe"]), ("second", &["tool.exe", "TOOL.cmd"])]);
+        let index = ExeIndex::build_with(Some(fake.path_var.clone()), windows(), NameCase::Insensitive);
+        assert_eq!(index.names().cloned().collect::<Vec<_>>(), vec!["Tool"]);
+        assert_eq!(index.get("TOOL").map(|x| x.path.clone()), Some(fake.file("first", "Tool.exe")));
+        assert_eq!(index.find_all("tool").len(), 3);
+
+        let index = ExeIndex::build_with(Some(fake.path_var.clone()), windows(), NameCase::Sensitive);
+        let mut names:Vec<_> = index.names().cloned().collect();
+        names.sort();
+        assert_eq!(names, vec!["TOOL", "Tool", "tool"]);
+        assert!(index.get("tOOL").is_none());
+    }
+
+    #[test]
+    fn case_setting_is_a_yes_or_no() {
+        assert_eq!(NameCase::from_setting(" Yes"), Some(NameCase::Sensitive));
+        assert_eq!(NameCase::from_setting("0"), Some(NameCase::Insensitive));
+        assert_eq!(NameCase::from_setting("sometimes"), None);
+    }
+
+    #[test]
+    fn typed_extension_picks_that_file() {
+        let fake = FakePath::new("typed-ext", &[("bin", &["foo.com", "foo.exe"])]);
+        assert_eq!(resolve(&fake.path_var, "foo.exe", &windows()).map(|x| x.path), Some(fake.file("bin", "foo.exe")));
+        assert!(resolve(&fake.path_var, "missing", &windows()).is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_needs_the_executable_bit() {
+        use std::os::unix::fs::PermissionsExt;
+        let fake = FakePath::new("unix", &[("bin", &["tool", "data.exe"])]);
+        fs::set_permissions(fake.file("bin", "tool"), fs::Permissions::from_mode(0o755)).unwrap();
+        let index = ExeIndex::build_with(Some(fake.path_var.clone()), NameRule::Unix, NameCase::Sensitive);
+        assert_eq!(index.names().cloned().collect::<Vec<_>>(), vec!["tool"]);
+        assert!(resolve(&fake.path_var, "data.exe", &NameRule::Unix).is_none());
+    }
+
+    #[test]
+    fn scripts_go_through_their_interpreter() {
+        let (program, prefix) = spawn_parts(path::Path::new("C:/tools/build.CMD"));
+        assert_eq!(program, "cmd");
+        assert_eq!(prefix, vec![ffi::OsString::from("/c"), ffi::OsString::from("C:/tools/build.CMD")]);
+        let (program, _) = spawn_parts(path::Path::new("C:/tools/setup.ps1"));
+        assert_eq!(program, "powershell");
+        let (program, prefix) = spawn_parts(path::Path::new("C:/tools/cargo.exe"));
+        assert_eq!(program, "C:/tools/cargo.exe");
+        assert!(prefix.is_empty());
+    }
+}
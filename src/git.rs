@@ -0,0 +1,527 @@
+// Everything the prompt wants to know about the git repo we're standing in.
+
+use std::{path, process, time};
+
+use crate::TimedFailure;
+
+/// What's going on in the repository, as much as we could find out within the time budget
+#[derive(Debug, Clone, Default)]
+pub struct GitStatus {
+    /// Branch name, or the short commit hash when HEAD is detached
+    pub head: String,
+    pub detached: bool,
+    /// Full hash of the commit that's checked out, None on a branch without commits
+    pub oid: Option<String>,
+    pub staged: usize,
+    pub unstaged: usize,
+    pub untracked: usize,
+    pub conflicted: usize,
+    pub ahead: usize,
+    pub behind: usize,
+    pub stashes: usize,
+    /// Rebase, merge, cherry-pick, etc. that is waiting on the user to finish it
+    pub operation: Option<&'static str>,
+    /// Set when `git status` didn't finish in time and only the head is known
+    pub timed_out: bool,
+}
+
+impl GitStatus {
+    /// The text that goes in the prompt's git segment, i.e. `main ↑1 +2 !1 ?3 ≡1 |REBASE`
+    pub fn summary(&self) -> String {
+        let mut s = self.head.clone();
+        if self.detached {
+            s = format!("@{}", s);
+        }
+        let counters = [
+            ("↑", self.ahead),
+            ("↓", self.behind),
+            ("✖", self.conflicted),
+            ("+", self.staged),
+            ("!", self.unstaged),
+            ("?", self.untracked),
+            ("≡", self.stashes),
+        ];
+        for (symbol, amount) in counters.iter() {
+            if *amount > 0 {
+                s.push_str(&format!(" {}{}", symbol, amount));
+            }
+        }
+        if let Some(op) = self.operation {
+            s.push_str(&format!(" |{}", op));
+        }
+        if self.timed_out {
+            s.push_str(" …");
+        }
+        return s;
+    }
+}
+
+/// Runs a git command and gives up on it once `budget` runs out, so a huge repo can't hang the prompt
+fn git_output(dir: &path::Path, args: &[&str], budget: time::Duration) -> Result<String, TimedFailure> {
+    let mut req = process::Command::new("git");
+    req.args(args).current_dir(dir);
+    return crate::timed_output(&mut req, budget).map(|buf| String::from_utf8_lossy(&buf).to_string());
+}
+
+/// Where a repository keeps its stuff
+pub struct Repo {
+    /// The `.git` directory, or for worktrees and submodules wherever the `.git` file points to
+    pub git_dir: path::PathBuf,
+    /// Shared refs and objects, only differs from `git_dir` for linked worktrees
+    pub common_dir: path::PathBuf,
+}
+
+pub enum Head {
+    /// `oid` is None for a branch without any commits yet
+    Branch { name: String, oid: Option<String> },
+    Detached(String),
+}
+
+/// Reads `gitdir: <path>` out of a `.git` file, relative paths are relative to the file's directory
+fn read_gitdir_file(file: &path::Path) -> Option<path::PathBuf> {
+    let content = std::fs::read_to_string(file).ok()?;
+    let target = content.lines().next()?.strip_prefix("gitdir:")?.trim();
+    let target = path::PathBuf::from(target);
+    if target.is_absolute() {
+        return Some(target);
+    }
+    return Some(file.parent()?.join(target));
+}
+
+fn repo_from_git_dir(git_dir: path::PathBuf) -> Repo {
+    let common_dir = match std::fs::read_to_string(git_dir.join("commondir")) {
+        Ok(content) => {
+            let common = path::PathBuf::from(content.trim());
+            if common.is_absolute() { common } else { git_dir.join(common) }
+        },
+        Err(_) => git_dir.clone(),
+    };
+    return Repo { git_dir, common_dir };
+}
+
+/// Finds the repository `dir` belongs to by walking up the directory tree, the same way git does it
+/// (minus the ceiling directories and safe.directory checks). `$GIT_DIR` wins when it's set.
+pub fn discover_repo(dir: &path::Path) -> Option<Repo> {
+    if let Some(git_dir) = std::env::var_os("GIT_DIR") {
+        let git_dir = dir.join(git_dir);
+        if !git_dir.join("HEAD").is_file() {
+            return None;
+        }
+        return Some(repo_from_git_dir(git_dir));
+    }
+    for ancestor in dir.ancestors() {
+        let dot_git = ancestor.join(".git");
+        let git_dir = if dot_git.is_dir() {
+            dot_git
+        } else if dot_git.is_file() {
+            match read_gitdir_file(&dot_git) {
+                Some(x) => x,
+                None => continue,
+            }
+        } else {
+            continue;
+        };
+        // A `.git` without a HEAD is not a repository, just somebody's weird folder
+        if git_dir.join("HEAD").is_file() {
+            return Some(repo_from_git_dir(git_dir));
+        }
+    }
+    return None;
+}
+
+fn is_oid(s: &str) -> bool {
+    return (s.len() == 40 || s.len() == 64) && s.chars().all(|c| c.is_ascii_hexdigit());
+}
+
+/// Looks a ref up as a loose file first and then in `packed-refs`, follows symbolic refs a few levels deep
+fn resolve_ref(repo: &Repo, name: &str) -> Option<String> {
+    let mut name = name.to_string();
+    for _ in 0..5 {
+        let loose = std::fs::read_to_string(repo.common_dir.join(&name))
+            .or_else(|_| std::fs::read_to_string(repo.git_dir.join(&name)));
+        if let Ok(content) = loose {
+            let content = content.trim();
+            if let Some(target) = content.strip_prefix("ref:") {
+                name = target.trim().to_string();
+                continue;
+            }
+            return if is_oid(content) { Some(content.to_string()) } else { None };
+        }
+        let packed = std::fs::read_to_string(repo.common_dir.join("packed-refs")).ok()?;
+        for line in packed.lines() {
+            if line.starts_with('#') || line.starts_with('^') {
+                continue;
+            }
+            if let Some((oid, ref_name)) = line.split_once(' ') {
+                if ref_name.trim() == name && is_oid(oid) {
+                    return Some(oid.to_string());
+                }
+            }
+        }
+        return None;
+    }
+    return None;
+}
+
+/// Reads HEAD without asking git, None when it's in a format we don't understand (i.e. reftable)
+pub fn read_head(repo: &Repo) -> Option<Head> {
+    let content = std::fs::read_to_string(repo.git_dir.join("HEAD")).ok()?;
+    let content = content.trim();
+    if let Some(target) = content.strip_prefix("ref:") {
+        let target = target.trim();
+        let name = target.strip_prefix("refs/heads/").unwrap_or(target).to_string();
+        if name == ".invalid" {
+            return None;
+        }
+        return Some(Head::Branch { name, oid: resolve_ref(repo, target) });
+    }
+    if is_oid(content) {
+        return Some(Head::Detached(content.to_string()));
+    }
+    return None;
+}
+
+/// Which operation the repo is in the middle of, judging by the files git leaves around in its directory
+fn query_operation(git_dir: &path::Path) -> Option<&'static str> {
+    if git_dir.join("rebase-merge").is_dir() {
+        return Some("REBASE");
+    }
+    if git_dir.join("rebase-apply").is_dir() {
+        if git_dir.join("rebase-apply").join("applying").exists() {
+            return Some("AM");
+        }
+        return Some("REBASE");
+    }
+    let markers = [
+        ("MERGE_HEAD", "MERGE"),
+        ("CHERRY_PICK_HEAD", "CHERRY-PICK"),
+        ("REVERT_HEAD", "REVERT"),
+        ("BISECT_LOG", "BISECT"),
+    ];
+    for (file, name) in markers.iter() {
+        if git_dir.join(file).exists() {
+            return Some(name);
+        }
+    }
+    return None;
+}
+
+/// Cheap to compute fingerprint of a repository's state, when it hasn't changed neither has the branch
+#[derive(Debug, Clone, PartialEq)]
+pub struct RepoStamp {
+    git_dir: path::PathBuf,
+    head_modified: Option<time::SystemTime>,
+    index_modified: Option<time::SystemTime>,
+    oid: Option<String>,
+}
+
+pub fn query_repo_stamp(dir: &path::Path) -> Option<RepoStamp> {
+    let repo = discover_repo(dir)?;
+    let modified = |p: path::PathBuf| std::fs::metadata(p).and_then(|m| m.modified()).ok();
+    let oid = match read_head(&repo) {
+        Some(Head::Branch { oid, .. }) => oid,
+        Some(Head::Detached(oid)) => Some(oid),
+        None => None,
+    };
+    return Some(RepoStamp {
+        head_modified: modified(repo.git_dir.join("HEAD")),
+        index_modified: modified(repo.git_dir.join("index")),
+        git_dir: repo.git_dir,
+        oid,
+    });
+}
+
+/// Returns None when `dir` is not inside of a git repository. The repo and its HEAD are read straight from
+/// disk, git itself is only needed for the working tree counters and upstream info.
+pub fn query_git_status(dir: &path::Path, budget: time::Duration) -> Option<GitStatus> {
+    let start = time::Instant::now();
+    let repo = discover_repo(dir)?;
+
+    let mut status = GitStatus {
+        operation: query_operation(&repo.git_dir),
+        ..Default::default()
+    };
+    let head = read_head(&repo);
+    match head {
+        Some(Head::Branch { ref name, ref oid }) => {
+            status.head = name.clone();
+            status.oid = oid.clone();
+        },
+        Some(Head::Detached(ref oid)) => {
+            status.detached = true;
+            status.head = oid.chars().take(7).collect();
+            status.oid = Some(oid.clone());
+        },
+        None => {},
+    };
+
+    let remaining = budget.saturating_sub(start.elapsed());
+    let out = match git_output(dir, &["status", "--porcelain=v2", "--branch", "--show-stash"], remaining) {
+        Ok(out) => out,
+        Err(TimedFailure::Spawn) => return Some(status),
+        Err(failure) => {
+            status.timed_out = matches!(failure, TimedFailure::TimedOut);
+            // HEAD is in some format we can't read ourselves, it's still cheap for git to tell us. Only with
+            // whatever's left of the budget though, `git status` might have used it all up.
+            let remaining = budget.saturating_sub(start.elapsed());
+            if head.is_none() && !remaining.is_zero() {
+                match git_output(dir, &["rev-parse", "--abbrev-ref", "HEAD"], remaining) {
+                    Ok(name) if name.trim() != "HEAD" => status.head = name.trim().to_string(),
+                    _ => {
+                        status.detached = true;
+                        let remaining = budget.saturating_sub(start.elapsed());
+                        if remaining.is_zero() {
+                            return Some(status);
+                        }
+                        if let Ok(sha) = git_output(dir, &["rev-parse", "--short", "HEAD"], remaining) {
+                            status.head = sha.trim().to_string();
+                        }
+                    },
+                };
+            }
+            return Some(status);
+        },
+    };
+
+    parse_porcelain(&out, &mut status);
+    return Some(status);
+}
+
+/// Counts up the output of `git status --porcelain=v2 --branch --show-stash` into `status`
+fn parse_porcelain(out: &str, status: &mut GitStatus) {
+    for line in out.lines() {
+        if let Some(header) = line.strip_prefix("# ") {
+            let (key, value) = header.split_once(' ').unwrap_or((header, ""));
+            match key {
+                "branch.oid" => status.oid = Some(value.to_string()).filter(|x| is_oid(x)),
+                "branch.head" => {
+                    if value == "(detached)" {
+                        status.detached = true;
+                    } else {
+                        status.head = value.to_string();
+                    }
+                },
+                "branch.ab" => {
+                    for part in value.split(' ') {
+                        if let Some(n) = part.strip_prefix('+') {
+                            status.ahead = n.parse().unwrap_or(0);
+                        } else if let Some(n) = part.strip_prefix('-') {
+                            status.behind = n.parse().unwrap_or(0);
+                        }
+                    }
+                },
+                "stash" => status.stashes = value.parse().unwrap_or(0),
+                _ => {},
+            };
+            continue;
+        }
+        let mut fields = line.split(' ');
+        match fields.next() {
+            Some("1") | Some("2") => {
+                let xy:Vec<char> = fields.next().unwrap_or("..").chars().collect();
+                if xy.first().is_some_and(|c| *c != '.') {
+                    status.staged += 1;
+                }
+                if xy.get(1).is_some_and(|c| *c != '.') {
+                    status.unstaged += 1;
+                }
+            },
+            Some("u") => status.conflicted += 1,
+            Some("?") => status.untracked += 1,
+            _ => {},
+        };
+    }
+    if status.detached {
+        status.head = status.oid.as_deref().unwrap_or_default().chars().take(7).collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const OID_A: &str = "1111111111111111111111111111111111111111";
+    const OID_B: &str = "2222222222222222222222222222222222222222";
+
+    /// A scratch folder for laying out repositories by hand, cleaned up on drop
+    struct Scratch {
+        root: path::PathBuf,
+    }
+
+    impl Scratch {
+        fn new(test: &str) -> Self {
+            let root = std::env::temp_dir().join(format!("xeorvi-git-{}-{}", test, std::process::id()));
+            let _ = fs::remove_dir_all(&root);
+            fs::create_dir_all(&root).unwrap();
+            return Scratch { root };
+        }
+
+        /// Writes `content` to `file` relative to the root, creating the folders on the way
+        fn write(&self, file: &str, content: &str) -> path::PathBuf {
+            let file = self.root.join(file);
+            fs::create_dir_all(file.parent().unwrap()).unwrap();
+            fs::write(&file, content).unwrap();
+            return file;
+        }
+
+        fn repo(&self, dir: &str) -> Repo {
+            return repo_from_git_dir(self.root.join(dir));
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    fn same_dir(a: &path::Path, b: &path::Path) -> bool {
+        return fs::canonicalize(a).unwrap() == fs::canonicalize(b).unwrap();
+    }
+
+    #[test]
+    fn gitdir_files_point_to_the_real_repo() {
+        let scratch = Scratch::new("gitdir-file");
+        let worktree = scratch.write("wt/.git", &format!("gitdir: {}\n", scratch.root.join("main/.git/worktrees/wt").display()));
+        assert_eq!(read_gitdir_file(&worktree), Some(scratch.root.join("main/.git/worktrees/wt")));
+        // Submodules use a path relative to the `.git` file
+        let submodule = scratch.write("main/sub/.git", "gitdir: ../.git/modules/sub\n");
+        assert_eq!(read_gitdir_file(&submodule), Some(scratch.root.join("main/sub/../.git/modules/sub")));
+        let junk = scratch.write("junk/.git", "not a gitdir line\n");
+        assert_eq!(read_gitdir_file(&junk), None);
+    }
+
+    #[test]
+    fn repos_are_found_from_any_subfolder() {
+        let scratch = Scratch::new("discover");
+        scratch.write("main/.git/HEAD", "ref: refs/heads/main\n");
+        scratch.write("main/src/deep/file.rs", "");
+        let repo = discover_repo(&scratch.root.join("main/src/deep")).unwrap();
+        assert_eq!(repo.git_dir, scratch.root.join("main/.git"));
+        assert_eq!(repo.common_dir, repo.git_dir);
+        assert!(discover_repo(&scratch.root).is_none());
+    }
+
+    #[test]
+    fn worktrees_and_submodules_are_discovered() {
+        let scratch = Scratch::new("discover-linked");
+        scratch.write("main/.git/HEAD", "ref: refs/heads/main\n");
+        scratch.write("main/.git/worktrees/wt/HEAD", "ref: refs/heads/feature\n");
+        scratch.write("main/.git/worktrees/wt/commondir", "../..\n");
+        scratch.write("wt/.git", &format!("gitdir: {}\n", scratch.root.join("main/.git/worktrees/wt").display()));
+        let repo = discover_repo(&scratch.root.join("wt")).unwrap();
+        assert_eq!(repo.git_dir, scratch.root.join("main/.git/worktrees/wt"));
+        assert!(same_dir(&repo.common_dir, &scratch.root.join("main/.git")));
+
+        scratch.write("main/.git/modules/sub/HEAD", &format!("{}\n", OID_A));
+        scratch.write("main/sub/.git", "gitdir: ../.git/modules/sub\n");
+        let repo = discover_repo(&scratch.root.join("main/sub")).unwrap();
+        assert!(same_dir(&repo.git_dir, &scratch.root.join("main/.git/modules/sub")));
+    }
+
+    #[test]
+    fn a_git_folder_without_head_is_skipped() {
+        let scratch = Scratch::new("discover-weird");
+        scratch.write("main/.git/HEAD", "ref: refs/heads/main\n");
+        scratch.write("main/inner/.git/config", "");
+        let repo = discover_repo(&scratch.root.join("main/inner")).unwrap();
+        assert_eq!(repo.git_dir, scratch.root.join("main/.git"));
+    }
+
+    #[test]
+    fn refs_resolve_loose_packed_and_symbolic() {
+        let scratch = Scratch::new("refs");
+        scratch.write("main/.git/refs/heads/main", &format!("{}\n", OID_A));
+        scratch.write("main/.git/packed-refs", &format!(
+            "# pack-refs with: peeled fully-peeled sorted\n{} refs/remotes/origin/main\n^{}\n{} refs/heads/main\n",
+            OID_B, OID_A, OID_B,
+        ));
+        scratch.write("main/.git/refs/remotes/origin/HEAD", "ref: refs/remotes/origin/main\n");
+        let repo = scratch.repo("main/.git");
+        // The loose file wins over the stale packed entry
+        assert_eq!(resolve_ref(&repo, "refs/heads/main").as_deref(), Some(OID_A));
+        assert_eq!(resolve_ref(&repo, "refs/remotes/origin/main").as_deref(), Some(OID_B));
+        assert_eq!(resolve_ref(&repo, "refs/remotes/origin/HEAD").as_deref(), Some(OID_B));
+        assert_eq!(resolve_ref(&repo, "refs/heads/nope"), None);
+    }
+
+    #[test]
+    fn symbolic_ref_loops_give_up() {
+        let scratch = Scratch::new("refs-loop");
+        scratch.write("main/.git/refs/heads/a", "ref: refs/heads/b\n");
+        scratch.write("main/.git/refs/heads/b", "ref: refs/heads/a\n");
+        assert_eq!(resolve_ref(&scratch.repo("main/.git"), "refs/heads/a"), None);
+    }
+
+    #[test]
+    fn head_is_read_from_disk() {
+        let scratch = Scratch::new("head");
+        scratch.write("main/.git/HEAD", "ref: refs/heads/main\n");
+        scratch.write("main/.git/refs/heads/main", &format!("{}\n", OID_A));
+        let repo = scratch.repo("main/.git");
+        assert!(matches!(read_head(&repo), Some(Head::Branch { name, oid: Some(oid) }) if name == "main" && oid == OID_A));
+
+        // A branch without any commits has no ref file yet
+        scratch.write("main/.git/HEAD", "ref: refs/heads/fresh\n");
+        assert!(matches!(read_head(&repo), Some(Head::Branch { name, oid: None }) if name == "fresh"));
+
+        scratch.write("main/.git/HEAD", &format!("{}\n", OID_B));
+        assert!(matches!(read_head(&repo), Some(Head::Detached(oid)) if oid == OID_B));
+
+        // reftable repos leave a placeholder HEAD behind
+        scratch.write("main/.git/HEAD", "ref: refs/heads/.invalid\n");
+        assert!(read_head(&repo).is_none());
+    }
+
+    #[test]
+    fn worktree_heads_use_the_shared_refs() {
+        let scratch = Scratch::new("head-worktree");
+        scratch.write("main/.git/refs/heads/feature", &format!("{}\n", OID_B));
+        scratch.write("main/.git/worktrees/wt/HEAD", "ref: refs/heads/feature\n");
+        scratch.write("main/.git/worktrees/wt/commondir", "../..\n");
+        let repo = scratch.repo("main/.git/worktrees/wt");
+        assert!(matches!(read_head(&repo), Some(Head::Branch { name, oid: Some(oid) }) if name == "feature" && oid == OID_B));
+    }
+
+    fn parse(out: &str) -> GitStatus {
+        let mut status = GitStatus::default();
+        parse_porcelain(out, &mut status);
+        return status;
+    }
+
+    #[test]
+    fn porcelain_counts_everything() {
+        let status = parse(&[
+            &format!("# branch.oid {}", OID_A),
+            "# branch.head main",
+            "# branch.upstream origin/main",
+            "# branch.ab +2 -3",
+            "# stash 4",
+            "1 M. N... 100644 100644 100644 aaaaaaa aaaaaaa src/main.rs",
+            "1 .M N... 100644 100644 100644 aaaaaaa aaaaaaa src/git.rs",
+            "1 MM N... 100644 100644 100644 aaaaaaa aaaaaaa src/lang.rs",
+            "2 R. N... 100644 100644 100644 aaaaaaa aaaaaaa R100 new.rs\told.rs",
+            "u UU N... 100644 100644 100644 100644 aaaaaaa aaaaaaa aaaaaaa conflict.rs",
+            "? notes.txt",
+            "? todo.txt",
+        ].join("\n"));
+        assert_eq!(status.head, "main");
+        assert!(!status.detached);
+        assert_eq!(status.oid.as_deref(), Some(OID_A));
+        assert_eq!((status.ahead, status.behind, status.stashes), (2, 3, 4));
+        assert_eq!((status.staged, status.unstaged, status.conflicted, status.untracked), (3, 2, 1, 2));
+    }
+
+    #[test]
+    fn porcelain_detached_and_unborn() {
+        let status = parse(&format!("# branch.oid {}\n# branch.head (detached)\n", OID_A));
+        assert!(status.detached);
+        assert_eq!(status.head, "1111111");
+
+        let status = parse("# branch.oid (initial)\n# branch.head fresh\n");
+        assert_eq!(status.head, "fresh");
+        assert_eq!(status.oid, None);
+        assert_eq!((status.ahead, status.behind, status.stashes), (0, 0, 0));
+    }
+}
@@ -0,0 +1,114 @@
+// Colours for the line being typed, worked out from the same tokens the line will be run with.
+
+use crossterm::style::{ContentStyle, StyledContent, Stylize};
+
+use crate::builtins;
+use crate::lang::{self, Mark, Op, TokenKind};
+use crate::Shell;
+
+impl Shell {
+    /// Whether running `name` would find something: a function, a builtin or a program
+    fn resolves(&self, name: &str) -> bool {
+        if name.contains('/') {
+            let Ok(path) = crate::parse_path(&self.dir_path, name) else {
+                return false;
+            };
+            return path.is_file() && is_executable::is_executable(&path);
+        }
+        if self.find_function(name).is_some() || builtins::find(name, self.name_case()).is_some() {
+            return true;
+        }
+        return self.env_exes.contains(name);
+    }
+}
+
+fn mark_style(style: ContentStyle, mark: Mark) -> ContentStyle {
+    return match mark {
+        Mark::Quote => style.yellow(),
+        Mark::Var => style.magenta(),
+        Mark::Comment => ContentStyle::new().dark_grey(),
+    };
+}
+
+/// Splits `input` into styled chunks that print back exactly the same text
+pub fn highlight(shell: &Shell, input: &str) -> Vec<StyledContent<String>> {
+    let (tokens, mut marks) = lang::tokenize_marked(input);
+    // One style per byte, only the ones at char boundaries end up mattering
+    let mut styles = vec![ContentStyle::new(); input.len()];
+    let mut paint = |range: std::ops::Range<usize>, f: &dyn Fn(ContentStyle) -> ContentStyle| {
+        for style in styles[range.start.min(input.len())..range.end.min(input.len())].iter_mut() {
+            *style = f(*style);
+        }
+    };
+
+    let mut is_command = true;
+    // `for` and `function` are followed by a name rather than a command, `Some(true)` when a command comes after it
+    let mut expect_name:Option<bool> = None;
+    for tok in tokens.iter() {
+        match &tok.kind {
+            TokenKind::Op(op) => {
+                if matches!(op, Op::Semi | Op::Newline | Op::And | Op::Or | Op::Pipe | Op::Amp | Op::LParen) {
+                    is_command = true;
+                }
+                paint(tok.span.clone(), &|x| x.cyan());
+            },
+            TokenKind::Word(w) => {
+                let bare = w.as_bare().unwrap_or_default();
+                if let Some(then_command) = expect_name.take() {
+                    is_command = then_command;
+                    continue;
+                }
+                if is_command && lang::is_keyword(bare) && bare != "in" {
+                    paint(tok.span.clone(), &|x| x.blue().bold());
+                    expect_name = match bare {
+                        "for" => Some(false),
+                        "function" => Some(true),
+                        _ => None,
+                    };
+                    continue;
+                }
+                if is_command && bare.split_once('=').is_some_and(|(name, _)| !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')) {
+                    continue;
+                }
+                let text = shell.expand_word_joined(w);
+                if is_command {
+                    // `time` runs whatever comes after it, so that's still a command
+                    is_command = text == "time";
+                    if shell.resolves(&text) {
+                        paint(tok.span.clone(), &|x| x.green());
+                    } else {
+                        paint(tok.span.clone(), &|x| x.red());
+                    }
+                    continue;
+                }
+                if bare == "in" {
+                    paint(tok.span.clone(), &|x| x.blue().bold());
+                    continue;
+                }
+                if !text.is_empty() && shell.dir_path.join(&text).exists() {
+                    paint(tok.span.clone(), &|x| x.underlined());
+                }
+            },
+        };
+    }
+    // Variables sit inside of quotes and should win over them
+    marks.sort_by_key(|(_, mark)| *mark != Mark::Quote);
+    for (range, mark) in marks {
+        paint(range, &|x| mark_style(x, mark));
+    }
+
+    let mut out = Vec::new();
+    let mut run = String::new();
+    let mut run_style = ContentStyle::new();
+    for (idx, ch) in input.char_indices() {
+        if styles[idx] != run_style && !run.is_empty() {
+            out.push(StyledContent::new(run_style, std::mem::take(&mut run)));
+        }
+        run_style = styles[idx];
+        run.push(ch);
+    }
+    if !run.is_empty() {
+        out.push(StyledContent::new(run_style, run));
+    }
+    return out;
+}
@@ -0,0 +1,1328 @@
+// The tiny language xeorvi speaks: a tokenizer, a recursive descent parser that turns the tokens into a
+// tree and the tree walking interpreter that runs it. Not a posix shell, just enough of one to be useful.
+
+use std::ops::Range;
+use std::rc::Rc;
+
+use crossterm::style::Stylize;
+
+use crate::builtins::{self, Input, Io, Output};
+use crate::complete;
+use crate::{Shell, UWrite};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WordPart {
+    /// Plain text, `quoted` is set when it came from inside quotes or was escaped with a backslash
+    Lit { text: String, quoted: bool },
+    /// `$name`, `${name}` or one of the special parameters like `$1`, `$@` and `$#`
+    Var { name: String, quoted: bool },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Word {
+    pub parts: Vec<WordPart>,
+}
+
+impl Word {
+    /// The text of the word if it's a single unquoted literal, which is the only kind of word that can be a keyword
+    pub fn as_bare(&self) -> Option<&str> {
+        return match self.parts.as_slice() {
+            [WordPart::Lit { text, quoted: false }] => Some(text.as_str()),
+            _ => None,
+        };
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Semi,
+    Newline,
+    And,
+    Or,
+    Pipe,
+    Amp,
+    LParen,
+    RParen,
+    /// The numbers are the fd being redirected, what goes in front like the 2 in `2>`
+    Great(u32),
+    DGreat(u32),
+    /// `>&`, as in `2>&1`
+    GreatAnd(u32),
+    Less(u32),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    Word(Word),
+    Op(Op),
+}
+
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub kind: TokenKind,
+    /// Byte range of the token in the input it came from
+    pub span: Range<usize>,
+}
+
+pub type Block = Vec<Node>;
+
+#[derive(Debug)]
+pub enum Node {
+    /// `redirs` are the `>`, `>>`, `>&` and `<` with the file (or fd for `>&`) that follows each
+    Simple { assigns: Vec<(String, Word)>, words: Vec<Word>, redirs: Vec<(Op, Word)> },
+    Pipeline(Vec<Node>),
+    AndOr { left: Box<Node>, right: Box<Node>, and: bool },
+    If { branches: Vec<(Block, Block)>, otherwise: Option<Block> },
+    While { cond: Block, body: Block, until: bool },
+    /// `items` is None for `for x; do ...; done` which loops over the positional parameters
+    For { var: String, items: Option<Vec<Word>>, body: Block },
+    Group(Block),
+    Function { name: String, body: Rc<Node> },
+}
+
+const KEYWORDS: [&str; 14] = [
+    "if", "then", "elif", "else", "fi",
+    "while", "until", "do", "done",
+    "for", "in", "function", "{", "}",
+];
+
+pub fn is_keyword(word: &str) -> bool {
+    return KEYWORDS.contains(&word);
+}
+
+fn is_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    return match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => chars.all(|c| c.is_ascii_alphanumeric() || c == '_'),
+        _ => false,
+    };
+}
+
+
+/*
+ * ===================================
+ * | Tokenizer
+ * -----------------------------------
+ */
+
+struct WordBuilder {
+    parts: Vec<WordPart>,
+    start: Option<usize>,
+}
+
+impl WordBuilder {
+    fn begin(&mut self, at: usize) {
+        if self.start.is_none() {
+            self.start = Some(at);
+        }
+    }
+
+    fn push_char(&mut self, at: usize, ch: char, quoted: bool) {
+        self.begin(at);
+        if let Some(WordPart::Lit { text, quoted: q }) = self.parts.last_mut() {
+            if *q == quoted {
+                text.push(ch);
+                return;
+            }
+        }
+        self.parts.push(WordPart::Lit { text: ch.to_string(), quoted });
+    }
+
+    fn finish(&mut self, end: usize, tokens: &mut Vec<Token>) {
+        if let Some(start) = self.start.take() {
+            let parts = std::mem::take(&mut self.parts);
+            tokens.push(Token { kind: TokenKind::Word(Word { parts }), span: start..end });
+        }
+    }
+}
+
+type CharStream<'a> = std::iter::Peekable<std::str::CharIndices<'a>>;
+
+/// Reads the name following a `$`, returns None when the `$` should be taken literally
+fn tokenize_var(chars: &mut CharStream, lenient: bool) -> Result<Option<String>, String> {
+    let ch = match chars.peek() {
+        Some((_, c)) => *c,
+        None => return Ok(None),
+    };
+    if ch == '{' {
+        chars.next();
+        let mut name = String::new();
+        loop {
+            match chars.next() {
+                Some((_, '}')) => break,
+                Some((_, c)) => name.push(c),
+                None if lenient => return Ok(Some(name)),
+                None => return Err(String::from("Unterminated ${ in variable")),
+            };
+        }
+        let valid = is_name(&name) || (!name.is_empty() && name.chars().all(|c| c.is_ascii_digit())) || ["@", "#", "?"].contains(&name.as_str());
+        if !valid && !lenient {
+            return Err(format!("Bad substitution: ${{{}}}", name));
+        }
+        return Ok(Some(name));
+    }
+    if ch == '@' || ch == '#' || ch == '?' || ch.is_ascii_digit() {
+        chars.next();
+        return Ok(Some(ch.to_string()));
+    }
+    if ch.is_ascii_alphabetic() || ch == '_' {
+        let mut name = String::new();
+        while let Some((_, c)) = chars.peek() {
+            if !(c.is_ascii_alphanumeric() || *c == '_') {
+                break;
+            }
+            name.push(*c);
+            chars.next();
+        }
+        return Ok(Some(name));
+    }
+    return Ok(None);
+}
+
+/// Bits inside of words that the tokens alone don't tell apart, only collected for highlighting
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mark {
+    Quote,
+    Var,
+    Comment,
+}
+
+pub fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    return tokenize_with(input, false, &mut Vec::new());
+}
+
+/// Tokenizes input that's still being typed, unterminated quotes and such just end the last word instead of
+/// being an error. Used for completion and highlighting so they see the same words execution will.
+pub fn tokenize_partial(input: &str) -> Vec<Token> {
+    return tokenize_with(input, true, &mut Vec::new()).unwrap_or_default();
+}
+
+/// Same as `tokenize_partial` but also tells where the quotes, variables and comments are
+pub fn tokenize_marked(input: &str) -> (Vec<Token>, Vec<(Range<usize>, Mark)>) {
+    let mut marks = Vec::new();
+    let tokens = tokenize_with(input, true, &mut marks).unwrap_or_default();
+    return (tokens, marks);
+}
+
+fn tokenize_with(input: &str, lenient: bool, marks: &mut Vec<(Range<usize>, Mark)>) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut word = WordBuilder { parts: Vec::new(), start: None };
+    let mut chars = input.char_indices().peekable();
+    let mut end = 0;
+
+    while let Some((idx, ch)) = chars.next() {
+        end = idx + ch.len_utf8();
+        let op = match ch {
+            ' ' | '\t' | '\r' => {
+                word.finish(idx, &mut tokens);
+                continue;
+            },
+            '#' if word.start.is_none() => {
+                // Comments run until the end of the line, the newline itself is still a separator
+                while let Some((_, c)) = chars.peek() {
+                    if *c == '\n' {
+                        break;
+                    }
+                    chars.next();
+                }
+                let comment_end = chars.peek().map(|(nidx, _)| *nidx).unwrap_or(input.len());
+                marks.push((idx..comment_end, Mark::Comment));
+                continue;
+            },
+            '\n' => Op::Newline,
+            ';' => Op::Semi,
+            '(' => Op::LParen,
+            ')' => Op::RParen,
+            '<' | '>' => {
+                // Digits right up against it like `2>` are the fd to redirect, not an argument
+                let mut start = idx;
+                let mut fd = None;
+                if let [WordPart::Lit { text, quoted: false }] = word.parts.as_slice() {
+                    if text.chars().all(|c| c.is_ascii_digit()) {
+                        fd = Some(text.parse::<u32>().unwrap_or(u32::MAX));
+                        start = word.start.take().unwrap_or(idx);
+                        word.parts.clear();
+                    }
+                }
+                word.finish(idx, &mut tokens);
+                let follow = chars.peek().map(|(_, c)| *c).filter(|c| ch == '>' && ['>', '&'].contains(c));
+                if follow.is_some() {
+                    chars.next();
+                    end += 1;
+                }
+                let op = match (ch, follow) {
+                    ('<', _) => Op::Less(fd.unwrap_or(0)),
+                    (_, Some('>')) => Op::DGreat(fd.unwrap_or(1)),
+                    (_, Some(_)) => Op::GreatAnd(fd.unwrap_or(1)),
+                    _ => Op::Great(fd.unwrap_or(1)),
+                };
+                tokens.push(Token { kind: TokenKind::Op(op), span: start..end });
+                continue;
+            },
+            '&' | '|' => {
+                let doubled = matches!(chars.peek(), Some((_, c)) if *c == ch);
+                if doubled {
+                    chars.next();
+                    end += 1;
+                }
+                match (ch, doubled) {
+                    ('&', true) => Op::And,
+                    ('&', false) => Op::Amp,
+                    ('|', true) => Op::Or,
+                    _ => Op::Pipe,
+                }
+            },
+            '\\' => {
+                match chars.next() {
+                    // Line continuation
+                    Some((_, '\n')) => {},
+                    Some((nidx, nch)) => {
+                        word.push_char(idx, nch, true);
+                        end = nidx + nch.len_utf8();
+                    },
+                    None => word.push_char(idx, '\\', false),
+                };
+                continue;
+            },
+            '\'' => {
+                word.begin(idx);
+                if word.parts.is_empty() {
+                    // So that '' still counts as an (empty) argument
+                    word.parts.push(WordPart::Lit { text: String::new(), quoted: true });
+                }
+                loop {
+                    match chars.next() {
+                        Some((nidx, '\'')) => {
+                            end = nidx + 1;
+                            break;
+                        },
+                        Some((nidx, nch)) => word.push_char(nidx, nch, true),
+                        None if lenient => {
+                            end = input.len();
+                            break;
+                        },
+                        None => return Err(String::from("Unterminated ' quote")),
+                    };
+                }
+                marks.push((idx..end, Mark::Quote));
+                continue;
+            },
+            '"' => {
+                word.begin(idx);
+                if word.parts.is_empty() {
+                    word.parts.push(WordPart::Lit { text: String::new(), quoted: true });
+                }
+                loop {
+                    match chars.next() {
+                        Some((nidx, '"')) => {
+                            end = nidx + 1;
+                            break;
+                        },
+                        Some((nidx, '\\')) => {
+                            match chars.peek() {
+                                Some((_, c)) if ['"', '\\', '$', '`', '\n'].contains(c) => {
+                                    let c = *c;
+                                    chars.next();
+                                    if c != '\n' {
+                                        word.push_char(nidx, c, true);
+                                    }
+                                },
+                                _ => word.push_char(nidx, '\\', true),
+                            };
+                        },
+                        Some((nidx, '$')) => match tokenize_var(&mut chars, lenient)? {
+                            Some(name) => {
+                                let var_end = chars.peek().map(|(x, _)| *x).unwrap_or(input.len());
+                                marks.push((nidx..var_end, Mark::Var));
+                                word.parts.push(WordPart::Var { name, quoted: true });
+                            },
+                            None => word.push_char(nidx, '$', true),
+                        },
+                        Some((nidx, nch)) => word.push_char(nidx, nch, true),
+                        None if lenient => {
+                            end = input.len();
+                            break;
+                        },
+                        None => return Err(String::from("Unterminated \" quote")),
+                    };
+                }
+                marks.push((idx..end, Mark::Quote));
+                continue;
+            },
+            '$' => {
+                match tokenize_var(&mut chars, lenient)? {
+                    Some(name) => {
+                        word.begin(idx);
+                        word.parts.push(WordPart::Var { name, quoted: false });
+                    },
+                    None => word.push_char(idx, '$', false),
+                };
+                end = match chars.peek() {
+                    Some((nidx, _)) => *nidx,
+                    None => input.len(),
+                };
+                if word.parts.last().is_some_and(|x| matches!(x, WordPart::Var { .. })) {
+                    marks.push((idx..end, Mark::Var));
+                }
+                continue;
+            },
+            _ => {
+                word.push_char(idx, ch, false);
+                continue;
+            },
+        };
+        word.finish(idx, &mut tokens);
+        tokens.push(Token { kind: TokenKind::Op(op), span: idx..end });
+    }
+    word.finish(end.max(input.len()), &mut tokens);
+
+    return Ok(tokens);
+}
+
+
+/*
+ * ===================================
+ * | Parser
+ * -----------------------------------
+ */
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+/// Parses a whole chunk of input, be it a line typed at the prompt or an entire script
+pub fn parse_user_input(input: &str) -> Result<Block, String> {
+    let mut parser = Parser { tokens: tokenize(input)?, pos: 0 };
+    let block = parser.parse_list(&[])?;
+    if let Some(tok) = parser.tokens.get(parser.pos) {
+        return Err(format!("Unexpected `{}`", &input[tok.span.clone()]));
+    }
+    return Ok(block);
+}
+
+/// Whether `input` stops partway through something more lines could finish: an open quote, a trailing
+/// `\`, `&&` or `|`, an `if` still missing its `fi` and so on. Actual mistakes don't count, those are
+/// errors no matter what comes after.
+pub fn is_incomplete(input: &str) -> bool {
+    let trimmed = input.strip_suffix('\n').unwrap_or(input);
+    if (trimmed.len() - trimmed.trim_end_matches('\\').len()) % 2 == 1 {
+        return true;
+    }
+    let tokens = match tokenize(input) {
+        Ok(x) => x,
+        // Only running out of input leaves something unterminated, `${a b}` and the like are just wrong
+        Err(err) => return err.starts_with("Unterminated"),
+    };
+    let mut parser = Parser { tokens, pos: 0 };
+    return parser.parse_list(&[]).is_err() && parser.pos >= parser.tokens.len();
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&TokenKind> {
+        return self.tokens.get(self.pos).map(|t| &t.kind);
+    }
+
+    fn peek_keyword(&self) -> Option<&str> {
+        return match self.peek() {
+            Some(TokenKind::Word(w)) => w.as_bare().filter(|x| is_keyword(x)),
+            _ => None,
+        };
+    }
+
+    fn peek_op(&self) -> Option<Op> {
+        return match self.peek() {
+            Some(TokenKind::Op(op)) => Some(*op),
+            _ => None,
+        };
+    }
+
+    fn skip_separators(&mut self) {
+        while let Some(Op::Semi | Op::Newline) = self.peek_op() {
+            self.pos += 1;
+        }
+    }
+
+    fn skip_newlines(&mut self) {
+        while let Some(Op::Newline) = self.peek_op() {
+            self.pos += 1;
+        }
+    }
+
+    fn expect_keyword(&mut self, kw: &str) -> Result<(), String> {
+        if self.peek_keyword() == Some(kw) {
+            self.pos += 1;
+            return Ok(());
+        }
+        return match self.peek() {
+            None => Err(format!("Unexpected end of input, expected `{}`", kw)),
+            Some(TokenKind::Word(w)) => Err(format!("Expected `{}` but found `{}`", kw, describe_word(w))),
+            Some(TokenKind::Op(op)) => Err(format!("Expected `{}` but found `{}`", kw, describe_op(*op))),
+        };
+    }
+
+    /// Parses commands separated by `;` or newlines until the end of input or one of `terms` shows up where a
+    /// command would start
+    fn parse_list(&mut self, terms: &[&str]) -> Result<Block, String> {
+        let mut block = Vec::new();
+        loop {
+            self.skip_separators();
+            if self.peek().is_none() {
+                break;
+            }
+            if let Some(kw) = self.peek_keyword() {
+                if terms.contains(&kw) {
+                    break;
+                }
+            }
+            block.push(self.parse_and_or()?);
+            match self.peek() {
+                None | Some(TokenKind::Op(Op::Semi | Op::Newline)) => {},
+                Some(TokenKind::Op(Op::Amp)) => return Err(String::from("Background jobs are not supported, yet!")),
+                Some(TokenKind::Op(op)) => return Err(format!("Unexpected `{}`", describe_op(*op))),
+                Some(TokenKind::Word(w)) => return Err(format!("Unexpected `{}`", describe_word(w))),
+            };
+        }
+        return Ok(block);
+    }
+
+    fn parse_and_or(&mut self) -> Result<Node, String> {
+        let mut left = self.parse_pipeline()?;
+        while let Some(op @ (Op::And | Op::Or)) = self.peek_op() {
+            self.pos += 1;
+            self.skip_newlines();
+            let right = self.parse_pipeline()?;
+            left = Node::AndOr { left: Box::new(left), right: Box::new(right), and: op == Op::And };
+        }
+        return Ok(left);
+    }
+
+    fn parse_pipeline(&mut self) -> Result<Node, String> {
+        let mut stages = vec![self.parse_command()?];
+        while let Some(Op::Pipe) = self.peek_op() {
+            self.pos += 1;
+            self.skip_newlines();
+            stages.push(self.parse_command()?);
+        }
+        if stages.len() == 1 {
+            return Ok(stages.remove(0));
+        }
+        return Ok(Node::Pipeline(stages));
+    }
+
+    fn parse_command(&mut self) -> Result<Node, String> {
+        match self.peek_keyword() {
+            Some("if") => return self.parse_if(),
+            Some("while") => return self.parse_while(false),
+            Some("until") => return self.parse_while(true),
+            Some("for") => return self.parse_for(),
+            Some("{") => {
+                self.pos += 1;
+                let body = self.parse_list(&["}"])?;
+                self.expect_keyword("}")?;
+                return Ok(Node::Group(body));
+            },
+            Some("function") => {
+                self.pos += 1;
+                let name = match self.peek() {
+                    Some(TokenKind::Word(w)) => w.as_bare().map(|x| x.to_string()),
+                    _ => None,
+                };
+                let name = match name {
+                    Some(name) if !is_keyword(&name) => name,
+                    _ => return Err(String::from("Expected a function name after `function`")),
+                };
+                self.pos += 1;
+                if let Some(Op::LParen) = self.peek_op() {
+                    self.pos += 1;
+                    if self.peek_op() != Some(Op::RParen) {
+                        return Err(String::from("Expected `)` after `(` in function definition"));
+                    }
+                    self.pos += 1;
+                }
+                return self.parse_function_body(name);
+            },
+            Some(kw) if kw != "in" => return Err(format!("Unexpected `{}`", kw)),
+            _ => {},
+        };
+
+        // name() { ... }
+        if let (Some(TokenKind::Word(w)), Some(Token { kind: TokenKind::Op(Op::LParen), .. })) = (self.peek(), self.tokens.get(self.pos + 1)) {
+            let name = match w.as_bare() {
+                Some(name) => name.to_string(),
+                None => return Err(String::from("Invalid function name")),
+            };
+            self.pos += 2;
+            if self.peek_op() != Some(Op::RParen) {
+                return Err(String::from("Expected `)` after `(` in function definition"));
+            }
+            self.pos += 1;
+            return self.parse_function_body(name);
+        }
+
+        return self.parse_simple();
+    }
+
+    fn parse_function_body(&mut self, name: String) -> Result<Node, String> {
+        self.skip_newlines();
+        let body = self.parse_command()?;
+        return Ok(Node::Function { name, body: Rc::new(body) });
+    }
+
+    fn parse_simple(&mut self) -> Result<Node, String> {
+        let mut assigns = Vec::new();
+        let mut words = Vec::new();
+        let mut redirs = Vec::new();
+        loop {
+            match self.peek() {
+                Some(TokenKind::Word(w)) => {
+                    let w = w.clone();
+                    self.pos += 1;
+                    if words.is_empty() {
+                        if let Some(assign) = split_assignment(&w) {
+                            assigns.push(assign);
+                            continue;
+                        }
+                    }
+                    words.push(w);
+                },
+                Some(TokenKind::Op(op @ (Op::Great(fd) | Op::DGreat(fd) | Op::GreatAnd(fd) | Op::Less(fd)))) => {
+                    let (op, fd) = (*op, *fd);
+                    match op {
+                        Op::Less(_) if fd != 0 => return Err(format!("Only stdin (0) can be read from with `<`, not {}", fd)),
+                        Op::Great(_) | Op::DGreat(_) | Op::GreatAnd(_) if fd != 1 && fd != 2 => {
+                            return Err(format!("Only stdout (1) and stderr (2) can be redirected, not {}", fd));
+                        },
+                        _ => {},
+                    };
+                    self.pos += 1;
+                    match self.peek() {
+                        Some(TokenKind::Word(w)) => redirs.push((op, w.clone())),
+                        _ if matches!(op, Op::GreatAnd(_)) => return Err(String::from("Expected 1 or 2 after `>&`")),
+                        _ => return Err(format!("Expected a file name after `{}`", describe_op(op))),
+                    };
+                    self.pos += 1;
+                },
+                Some(TokenKind::Op(Op::LParen)) if words.is_empty() && assigns.is_empty() => {
+                    return Err(String::from("Subshells are not supported, yet!"));
+                },
+                _ => break,
+            };
+        }
+        if words.is_empty() && assigns.is_empty() && redirs.is_empty() {
+            return match self.peek() {
+                Some(TokenKind::Op(op)) => Err(format!("Unexpected `{}`", describe_op(*op))),
+                _ => Err(String::from("Unexpected end of input")),
+            };
+        }
+        return Ok(Node::Simple { assigns, words, redirs });
+    }
+
+    fn parse_if(&mut self) -> Result<Node, String> {
+        let mut branches = Vec::new();
+        let mut otherwise = None;
+        self.expect_keyword("if")?;
+        loop {
+            let cond = self.parse_list(&["then"])?;
+            self.expect_keyword("then")?;
+            let body = self.parse_list(&["elif", "else", "fi"])?;
+            if cond.is_empty() || body.is_empty() {
+                return Err(String::from("Empty condition or body in `if`"));
+            }
+            branches.push((cond, body));
+            match self.peek_keyword() {
+                Some("elif") => {
+                    self.pos += 1;
+                    continue;
+                },
+                Some("else") => {
+                    self.pos += 1;
+                    otherwise = Some(self.parse_list(&["fi"])?);
+                },
+                _ => {},
+            };
+            break;
+        }
+        self.expect_keyword("fi")?;
+        return Ok(Node::If { branches, otherwise });
+    }
+
+    fn parse_while(&mut self, until: bool) -> Result<Node, String> {
+        self.pos += 1;
+        let cond = self.parse_list(&["do"])?;
+        self.expect_keyword("do")?;
+        let body = self.parse_list(&["done"])?;
+        self.expect_keyword("done")?;
+        if cond.is_empty() || body.is_empty() {
+            return Err(format!("Empty condition or body in `{}`", if until { "until" } else { "while" }));
+        }
+        return Ok(Node::While { cond, body, until });
+    }
+
+    fn parse_for(&mut self) -> Result<Node, String> {
+        self.pos += 1;
+        let var = match self.peek() {
+            Some(TokenKind::Word(w)) => w.as_bare().filter(|x| is_name(x)).map(|x| x.to_string()),
+            _ => None,
+        };
+        let var = match var {
+            Some(x) => x,
+            None => return Err(String::from("Expected a variable name after `for`")),
+        };
+        self.pos += 1;
+        self.skip_newlines();
+        let mut items = None;
+        if self.peek_keyword() == Some("in") {
+            self.pos += 1;
+            let mut words = Vec::new();
+            while let Some(TokenKind::Word(w)) = self.peek() {
+                words.push(w.clone());
+                self.pos += 1;
+            }
+            items = Some(words);
+        }
+        self.skip_separators();
+        self.expect_keyword("do")?;
+        let body = self.parse_list(&["done"])?;
+        self.expect_keyword("done")?;
+        return Ok(Node::For { var, items, body });
+    }
+}
+
+/// Splits `NAME=value` into its name and value word
+fn split_assignment(word: &Word) -> Option<(String, Word)> {
+    let (text, rest) = match word.parts.split_first() {
+        Some((WordPart::Lit { text, quoted: false }, rest)) => (text, rest),
+        _ => return None,
+    };
+    let idx = text.find('=')?;
+    let name = &text[..idx];
+    if !is_name(name) {
+        return None;
+    }
+    let mut parts = Vec::new();
+    if idx + 1 < text.len() {
+        parts.push(WordPart::Lit { text: text[idx + 1..].to_string(), quoted: false });
+    }
+    parts.extend(rest.iter().cloned());
+    return Some((name.to_string(), Word { parts }));
+}
+
+fn describe_word(word: &Word) -> String {
+    let mut s = String::new();
+    for part in word.parts.iter() {
+        match part {
+            WordPart::Lit { text, .. } => s.push_str(text),
+            WordPart::Var { name, .. } => s.push_str(&format!("${}", name)),
+        };
+    }
+    return s;
+}
+
+fn describe_op(op: Op) -> &'static str {
+    return match op {
+        Op::Semi => ";",
+        Op::Newline => "newline",
+        Op::And => "&&",
+        Op::Or => "||",
+        Op::Pipe => "|",
+        Op::Amp => "&",
+        Op::LParen => "(",
+        Op::RParen => ")",
+        Op::Great(2) => "2>",
+        Op::Great(_) => ">",
+        Op::DGreat(2) => "2>>",
+        Op::DGreat(_) => ">>",
+        Op::GreatAnd(2) => "2>&",
+        Op::GreatAnd(_) => ">&",
+        Op::Less(_) => "<",
+    };
+}
+
+
+/*
+ * ===================================
+ * | Interpreter
+ * -----------------------------------
+ */
+
+/// Accumulates the fields a word expands into, unquoted variables get split on whitespace
+struct Fields {
+    out: Vec<String>,
+    cur: String,
+    has: bool,
+}
+
+impl Fields {
+    fn push(&mut self, s: &str) {
+        self.cur.push_str(s);
+        self.has = true;
+    }
+
+    fn split(&mut self) {
+        if self.has {
+            self.out.push(std::mem::take(&mut self.cur));
+            self.has = false;
+        }
+    }
+
+    fn push_unquoted(&mut self, value: &str) {
+        if value.starts_with(char::is_whitespace) {
+            self.split();
+        }
+        for (i, piece) in value.split_whitespace().enumerate() {
+            if i > 0 {
+                self.split();
+            }
+            self.push(piece);
+        }
+        if value.ends_with(char::is_whitespace) {
+            self.split();
+        }
+    }
+}
+
+impl Shell {
+    pub fn exec_block(&mut self, block: &[Node]) -> Result<(), String> {
+        for node in block.iter() {
+            if self.stopped() {
+                break;
+            }
+            self.exec_node(node)?;
+        }
+        return Ok(());
+    }
+
+    fn exec_node(&mut self, node: &Node) -> Result<(), String> {
+        match node {
+            Node::Simple { assigns, words, redirs } => {
+                let mut env_vars = Vec::new();
+                for (name, value) in assigns.iter() {
+                    env_vars.push((name.clone(), self.expand_word_joined(value)));
+                }
+                let argv = self.expand_words(words);
+                let io = match self.redirect(redirs)? {
+                    Some(x) => x,
+                    None => return Ok(()),
+                };
+                if argv.is_empty() {
+                    for (name, value) in env_vars {
+                        self.assign(name, value);
+                    }
+                    self.last_status = 0;
+                    return Ok(());
+                }
+                return self.with_io(io, |shell| shell.call(argv, env_vars));
+            },
+            Node::Pipeline(stages) => {
+                // Nested pipelines (i.e. a function with one in it being piped somewhere) wait for their own programs only
+                let outer_children = std::mem::take(&mut self.pipeline_children);
+                let res = self.exec_pipeline(stages);
+                for mut child in std::mem::replace(&mut self.pipeline_children, outer_children) {
+                    let _ = child.wait();
+                }
+                res?;
+            },
+            Node::AndOr { left, right, and } => {
+                self.exec_node(left)?;
+                if (self.last_status == 0) == *and && !self.stopped() {
+                    self.exec_node(right)?;
+                }
+            },
+            Node::If { branches, otherwise } => {
+                for (cond, body) in branches.iter() {
+                    self.exec_block(cond)?;
+                    if self.last_status == 0 {
+                        return self.exec_block(body);
+                    }
+                }
+                match otherwise {
+                    Some(body) => self.exec_block(body)?,
+                    None => self.last_status = 0,
+                };
+            },
+            Node::While { cond, body, until } => {
+                let mut status = 0;
+                while !self.stopped() {
+                    self.exec_block(cond)?;
+                    if (self.last_status == 0) == *until {
+                        break;
+                    }
+                    self.exec_block(body)?;
+                    status = self.last_status;
+                }
+                self.last_status = status;
+            },
+            Node::For { var, items, body } => {
+                let items = match items {
+                    Some(words) => self.expand_words(words),
+                    None => self.positional.clone(),
+                };
+                self.last_status = 0;
+                for item in items {
+                    if self.stopped() {
+                        break;
+                    }
+                    self.vars.insert(var.clone(), item);
+                    self.exec_block(body)?;
+                }
+            },
+            Node::Group(body) => self.exec_block(body)?,
+            Node::Function { name, body } => {
+                self.functions.insert(name.clone(), body.clone());
+                self.last_status = 0;
+            },
+        };
+        return Ok(());
+    }
+
+    /// Runs every stage with its stdout going into the next one's stdin. Programs all get started first so
+    /// that whatever builtins and functions write has somebody reading it already, then those run right
+    /// here one after another. `$?` is the same for every stage, the status from before the pipeline.
+    fn exec_pipeline(&mut self, stages: &[Node]) -> Result<(), String> {
+        let mut ios = Vec::with_capacity(stages.len());
+        let mut stdin = self.io.stdin.try_clone()?;
+        for i in 0..stages.len() {
+            let (next_stdin, stdout) = if i + 1 < stages.len() {
+                builtins::pipe()?
+            } else {
+                (Input::Terminal, self.io.stdout.try_clone()?)
+            };
+            ios.push(Some(Io { stdin, stdout, stderr: self.io.stderr.try_clone()? }));
+            stdin = next_stdin;
+        }
+
+        let status_before = self.last_status;
+        // The last stage's status is the pipeline's, the index is for when it's a program that's yet to finish
+        let mut last_child = None;
+        let mut last_status = 0;
+        for (i, stage) in stages.iter().enumerate() {
+            if !self.is_program(stage) {
+                continue;
+            }
+            let io = ios[i].take().unwrap();
+            self.last_status = status_before;
+            let children = self.pipeline_children.len();
+            self.defer_wait = true;
+            // The stage's ends of the pipes get dropped right after, otherwise the next one never sees the end of its input
+            let res = self.with_io(io, |shell| shell.exec_node(stage));
+            self.defer_wait = false;
+            res?;
+            if i + 1 == stages.len() {
+                last_child = Some(children).filter(|x| *x < self.pipeline_children.len());
+                last_status = self.last_status;
+            }
+        }
+        for (i, stage) in stages.iter().enumerate() {
+            let io = match ios[i].take() {
+                Some(x) => x,
+                None => continue,
+            };
+            if self.should_quit {
+                break;
+            }
+            self.last_status = status_before;
+            self.with_io(io, |shell| shell.exec_node(stage))?;
+            if i + 1 == stages.len() {
+                last_status = self.last_status;
+            }
+        }
+        // Whatever didn't run still had its ends of the pipes, those have to go before anything gets waited on
+        drop(ios);
+
+        if let Some(idx) = last_child {
+            last_status = match self.pipeline_children[idx].wait() {
+                Ok(status) => crate::exit_status_code(&status),
+                Err(_) => 1,
+            };
+        }
+        self.last_status = last_status;
+        return Ok(());
+    }
+
+    /// Whether a pipeline stage is a plain program, the kind that runs on its own without the shell
+    fn is_program(&self, node: &Node) -> bool {
+        let words = match node {
+            Node::Simple { words, .. } => words,
+            _ => return false,
+        };
+        return match self.expand_words(words).first() {
+            Some(name) => self.find_function(name).is_none() && builtins::find(name, self.name_case()).is_none(),
+            None => false,
+        };
+    }
+
+    /// Whether whatever's running should stop: the shell is quitting or the pipe it writes to has nobody
+    /// reading anymore
+    fn stopped(&self) -> bool {
+        return self.should_quit || self.io.stdout.is_closed();
+    }
+
+    /// Runs `f` with `io` as where commands read and write, putting the previous one back after
+    fn with_io(&mut self, io: Io, f: impl FnOnce(&mut Self) -> Result<(), String>) -> Result<(), String> {
+        let saved = std::mem::replace(&mut self.io, io);
+        let res = f(self);
+        self.io = saved;
+        return res;
+    }
+
+    /// Opens the files of a command's redirections on top of the current io. `None` when one of them can't
+    /// be opened, that's been reported already and the command shouldn't run.
+    fn redirect(&mut self, redirs: &[(Op, Word)]) -> Result<Option<Io>, String> {
+        let mut io = self.io.try_clone()?;
+        for (op, word) in redirs.iter() {
+            let name = self.expand_word_joined(word);
+            // `2>&1` points at wherever the other one goes right now, so `>out 2>&1` puts both in out
+            let opened = match op {
+                Op::GreatAnd(_) => match name.as_str() {
+                    "1" => io.stdout.try_clone().map_err(|err| format!("{}\n", err)),
+                    "2" => io.stderr.try_clone().map_err(|err| format!("{}\n", err)),
+                    _ => Err(format!("`>&` takes 1 or 2, not `{}`\n", name)),
+                },
+                _ => crate::parse_path(&self.dir_path, &name).and_then(|path| {
+                    let res = match op {
+                        Op::Less(_) => std::fs::File::open(&path),
+                        Op::DGreat(_) => std::fs::OpenOptions::new().append(true).create(true).open(&path),
+                        _ => std::fs::File::create(&path),
+                    };
+                    return res.map(Output::File).map_err(|err| format!("{}: {}\n", name, err));
+                }),
+            };
+            let out = match opened {
+                Ok(x) => x,
+                Err(err) => {
+                    let mut stderr = std::io::stderr();
+                    stderr.uswrite("[?ERROR]".red())?;
+                    stderr.ubwrite(format!(" {}", err))?;
+                    self.last_status = 1;
+                    return Ok(None);
+                },
+            };
+            match (op, out) {
+                (Op::Less(_), Output::File(file)) => io.stdin = Input::File(file),
+                (Op::Great(2) | Op::DGreat(2) | Op::GreatAnd(2), out) => io.stderr = out,
+                (_, out) => io.stdout = out,
+            };
+        }
+        return Ok(Some(io));
+    }
+
+    /// Runs a user defined function if there's one named `argv[0]`, otherwise a builtin or program
+    pub fn call(&mut self, argv: Vec<String>, env_vars: Vec<(String, String)>) -> Result<(), String> {
+        if self.interactive {
+            self.record_usage(&argv);
+        }
+        if let Some(body) = self.find_function(&argv[0]) {
+            let saved = std::mem::replace(&mut self.positional, argv[1..].to_vec());
+            let res = self.exec_node(&body);
+            self.positional = saved;
+            return res;
+        }
+        return self.run_command(argv, env_vars);
+    }
+
+    /// The function `name` calls, going by the same case rules as any other command name
+    pub fn find_function(&self, name: &str) -> Option<Rc<Node>> {
+        if let Some(body) = self.functions.get(name) {
+            return Some(body.clone());
+        }
+        let case = self.name_case();
+        return self.functions.iter().find(|(x, _)| case.eq(x, name)).map(|(_, body)| body.clone());
+    }
+
+    /// Feeds the suggestion ranking with the command and whichever arguments are paths
+    fn record_usage(&mut self, argv: &[String]) {
+        complete::record_usage(self, &argv[0]);
+        for arg in argv[1..].iter() {
+            let path = self.dir_path.join(arg);
+            if path.exists() {
+                // Same shape as the paths completion looks up, no trailing slashes or `.`s
+                let path:std::path::PathBuf = path.components().collect();
+                complete::record_usage(self, &path.to_string_lossy());
+            }
+        }
+    }
+
+    /// `NAME=value` on its own. Names that came from the environment stay there so programs see the change,
+    /// `PATH=...` has to actually change where programs are found. Anything else is only the shell's.
+    fn assign(&mut self, name: String, value: String) {
+        if std::env::var_os(&name).is_none() {
+            self.vars.insert(name, value);
+            return;
+        }
+        self.vars.remove(&name);
+        std::env::set_var(name, value);
+        // PATH might have just changed, what the index has is no good then
+        let case = self.name_case();
+        self.env_exes.refresh(case);
+    }
+
+    pub fn lookup_var(&self, name: &str) -> Option<String> {
+        if name == "#" {
+            return Some(self.positional.len().to_string());
+        }
+        if name == "?" {
+            return Some(self.last_status.to_string());
+        }
+        if name == "@" {
+            return Some(self.positional.join(" "));
+        }
+        if let Ok(n) = name.parse::<usize>() {
+            if n == 0 {
+                return Some(self.script_name.clone());
+            }
+            return self.positional.get(n - 1).cloned();
+        }
+        if let Some(value) = self.vars.get(name) {
+            return Some(value.clone());
+        }
+        return std::env::var(name).ok();
+    }
+
+    fn expand_word_into(&self, word: &Word, fields: &mut Fields) {
+        // `"$@"` is as many arguments as there are positional parameters, none included. The empty quoted
+        // bit the tokenizer leaves in front of it shouldn't make an argument out of nothing.
+        let quoted_at = word.parts.iter().any(|x| matches!(x, WordPart::Var { name, quoted: true } if name == "@"));
+        for part in word.parts.iter() {
+            match part {
+                WordPart::Lit { text, quoted } => {
+                    // An empty quoted string is still an argument, an empty unquoted one is nothing
+                    if (*quoted && !quoted_at) || !text.is_empty() {
+                        fields.push(text);
+                    }
+                },
+                WordPart::Var { name, quoted } if name == "@" => {
+                    for (i, arg) in self.positional.iter().enumerate() {
+                        if i > 0 {
+                            fields.split();
+                        }
+                        if *quoted {
+                            fields.push(arg);
+                        } else {
+                            fields.push_unquoted(arg);
+                        }
+                    }
+                },
+                WordPart::Var { name, quoted } => {
+                    let value = self.lookup_var(name).unwrap_or_default();
+                    if *quoted {
+                        fields.push(&value);
+                    } else {
+                        fields.push_unquoted(&value);
+                    }
+                },
+            };
+        }
+        fields.split();
+    }
+
+    pub fn expand_words(&self, words: &[Word]) -> Vec<String> {
+        let mut fields = Fields { out: Vec::new(), cur: String::new(), has: false };
+        for word in words.iter() {
+            self.expand_word_into(word, &mut fields);
+        }
+        return fields.out;
+    }
+
+    /// Expands a word without splitting it into several fields, what you want for `NAME=value`
+    pub fn expand_word_joined(&self, word: &Word) -> String {
+        let mut out = String::new();
+        for part in word.parts.iter() {
+            match part {
+                WordPart::Lit { text, .. } => out.push_str(text),
+                WordPart::Var { name, .. } => out.push_str(&self.lookup_var(name).unwrap_or_default()),
+            };
+        }
+        return out;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ops(input: &str) -> Vec<Op> {
+        return tokenize(input).unwrap().into_iter().filter_map(|t| match t.kind {
+            TokenKind::Op(op) => Some(op),
+            TokenKind::Word(_) => None,
+        }).collect();
+    }
+
+    fn redirs(input: &str) -> Vec<(Op, String)> {
+        return match parse_user_input(input).unwrap().remove(0) {
+            Node::Simple { redirs, .. } => redirs.into_iter().map(|(op, w)| (op, describe_word(&w))).collect(),
+            other => panic!("not a simple command: {:?}", other),
+        };
+    }
+
+    fn lit(text: &str, quoted: bool) -> WordPart {
+        return WordPart::Lit { text: text.to_string(), quoted };
+    }
+
+    fn var(name: &str, quoted: bool) -> WordPart {
+        return WordPart::Var { name: name.to_string(), quoted };
+    }
+
+    fn words(input: &str) -> Vec<Vec<WordPart>> {
+        return tokenize(input).unwrap().into_iter().filter_map(|t| match t.kind {
+            TokenKind::Word(w) => Some(w.parts),
+            TokenKind::Op(_) => None,
+        }).collect();
+    }
+
+    fn shell(positional: &[&str], vars: &[(&str, &str)]) -> Shell {
+        let mut shell = Shell::new("xeorvi", false).unwrap();
+        shell.positional = positional.iter().map(|x| x.to_string()).collect();
+        for (k, v) in vars.iter() {
+            shell.vars.insert(k.to_string(), v.to_string());
+        }
+        return shell;
+    }
+
+    fn expand(shell: &Shell, input: &str) -> Vec<String> {
+        let block = parse_user_input(input).unwrap();
+        let Node::Simple { words, .. } = &block[0] else { panic!() };
+        return shell.expand_words(words);
+    }
+
+    #[test]
+    fn tokenize_words_quotes_and_vars() {
+        assert_eq!(words(r"echo a\ b 'c d'"), vec![
+            vec![lit("echo", false)],
+            vec![lit("a", false), lit(" ", true), lit("b", false)],
+            vec![lit("c d", true)],
+        ]);
+        assert_eq!(words("x$HOME\"${1}\"$"), vec![
+            vec![lit("x", false), var("HOME", false), var("1", true), lit("$", false)],
+        ]);
+        assert_eq!(words(r#"'$x' "a\"b" '' "$y""#), vec![
+            vec![lit("$x", true)],
+            vec![lit("a\"b", true)],
+            vec![lit("", true)],
+            vec![lit("", true), var("y", true)],
+        ]);
+    }
+
+    #[test]
+    fn tokenize_ops_and_comments() {
+        assert_eq!(ops("a && b || c | d; e & # f | g\nh"), vec![Op::And, Op::Or, Op::Pipe, Op::Semi, Op::Amp, Op::Newline]);
+        assert_eq!(words("a # b c").len(), 1);
+        // Only at the start of a word
+        assert_eq!(words("a#b"), vec![vec![lit("a#b", false)]]);
+    }
+
+    #[test]
+    fn tokenize_errors_unless_partial() {
+        assert!(tokenize("echo 'abc").is_err());
+        assert!(tokenize("echo \"abc").is_err());
+        assert!(tokenize("echo ${abc").is_err());
+        assert_eq!(tokenize_partial("echo 'abc").len(), 2);
+    }
+
+    #[test]
+    fn parse_structure() {
+        let block = parse_user_input("X=1 a b; if c; then d; elif e; then f; else g; fi\nwhile h; do i; done").unwrap();
+        assert_eq!(block.len(), 3);
+        let Node::Simple { assigns, words, .. } = &block[0] else { panic!() };
+        assert_eq!((assigns.len(), words.len()), (1, 2));
+        let Node::If { branches, otherwise } = &block[1] else { panic!() };
+        assert_eq!((branches.len(), otherwise.is_some()), (2, true));
+        assert!(matches!(&block[2], Node::While { until: false, .. }));
+
+        let block = parse_user_input("a && b | c || d").unwrap();
+        let Node::AndOr { left, and: false, .. } = &block[0] else { panic!() };
+        let Node::AndOr { right, and: true, .. } = left.as_ref() else { panic!() };
+        assert!(matches!(right.as_ref(), Node::Pipeline(stages) if stages.len() == 2));
+    }
+
+    #[test]
+    fn parse_functions_and_for() {
+        let block = parse_user_input("f() { a; }; function g { b; }; for x in 1 2; do c; done; for y; do d; done").unwrap();
+        assert!(matches!(&block[0], Node::Function { name, .. } if name == "f"));
+        assert!(matches!(&block[1], Node::Function { name, .. } if name == "g"));
+        assert!(matches!(&block[2], Node::For { var, items: Some(items), .. } if var == "x" && items.len() == 2));
+        assert!(matches!(&block[3], Node::For { var, items: None, .. } if var == "y"));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(parse_user_input("if a; then b").is_err());
+        assert!(parse_user_input("while a; do; done").is_err());
+        assert!(parse_user_input("a &").is_err());
+        assert!(parse_user_input("a |").is_err());
+        assert!(parse_user_input("fi").is_err());
+        assert!(parse_user_input("echo >").is_err());
+    }
+
+    #[test]
+    fn expand_splits_unquoted_vars_only() {
+        let shell = shell(&[], &[("V", " a  b "), ("E", "")]);
+        assert_eq!(expand(&shell, "echo $V \"$V\" x${V}y"), vec!["echo", "a", "b", " a  b ", "x", "a", "b", "y"]);
+        assert_eq!(expand(&shell, "echo $E '' \"$E\" $NOPE_NOT_SET"), vec!["echo", "", ""]);
+    }
+
+    #[test]
+    fn expand_positional_parameters() {
+        let shell = shell(&["a b", "c"], &[]);
+        assert_eq!(expand(&shell, "f \"$@\" $# $1"), vec!["f", "a b", "c", "2", "a", "b"]);
+        assert_eq!(expand(&shell, "f $@"), vec!["f", "a", "b", "c"]);
+        assert_eq!(expand(&shell, "f x\"$@\"y"), vec!["f", "xa b", "cy"]);
+    }
+
+    #[test]
+    fn expand_quoted_at_without_parameters_is_nothing() {
+        let shell = shell(&[], &[]);
+        assert_eq!(expand(&shell, "f \"$@\""), vec!["f"]);
+        assert_eq!(expand(&shell, "f \"${@}\" $@"), vec!["f"]);
+        assert_eq!(expand(&shell, "f x\"$@\""), vec!["f", "x"]);
+        assert_eq!(expand(&shell, "f \"\""), vec!["f", ""]);
+    }
+
+    #[test]
+    fn incomplete_input() {
+        for input in ["echo 'a\n", "echo \"a\n", "echo ${a", "echo a \\\n", "a &&\n", "a |", "if a; then\n", "if a; then b; fi; while c\n", "f() {\n", "for x in a b\n"] {
+            assert!(is_incomplete(input), "{:?} should be incomplete", input);
+        }
+        for input in ["echo a\n", "echo a \\\\\n", "if a; then b; fi\n", "fi\n", "a &\n", "echo ${a b}\n", "echo 3>x\n", ""] {
+            assert!(!is_incomplete(input), "{:?} should be complete", input);
+        }
+    }
+
+    #[test]
+    fn exit_with_a_bad_status_still_exits() {
+        let mut script = shell(&[], &[]);
+        script.run_source("exit nope; AFTER=1").unwrap();
+        assert!(script.should_quit);
+        assert_eq!(script.last_status, 2);
+        assert!(script.lookup_var("AFTER").is_none());
+
+        let mut script = shell(&[], &[]);
+        script.run_source("exit 300; AFTER=1").unwrap();
+        assert_eq!((script.should_quit, script.last_status), (true, 44));
+        assert!(script.lookup_var("AFTER").is_none());
+    }
+
+    #[test]
+    fn numbered_redirections() {
+        assert_eq!(ops("a 2>x 2>>y >&2 2>&1 <z"), vec![Op::Great(2), Op::DGreat(2), Op::GreatAnd(1), Op::GreatAnd(2), Op::Less(0)]);
+        assert_eq!(redirs("ls /nope 2>/dev/null"), vec![(Op::Great(2), String::from("/dev/null"))]);
+        assert_eq!(redirs("echo x 2>&1"), vec![(Op::GreatAnd(2), String::from("1"))]);
+    }
+
+    #[test]
+    fn digits_apart_from_the_redirection_are_arguments() {
+        let block = parse_user_input("echo 2 >x a2>y").unwrap();
+        let Node::Simple { words, redirs, .. } = &block[0] else { panic!() };
+        assert_eq!(words.iter().map(describe_word).collect::<Vec<_>>(), vec!["echo", "2", "a2"]);
+        assert_eq!(redirs.iter().map(|(op, _)| *op).collect::<Vec<_>>(), vec![Op::Great(1), Op::Great(1)]);
+        // Quoted digits are an argument too
+        assert_eq!(ops("echo '2'>x"), vec![Op::Great(1)]);
+    }
+
+    #[test]
+    fn unsupported_fds_are_errors() {
+        assert!(parse_user_input("echo 3>x").is_err());
+        assert!(parse_user_input("cat 1<x").is_err());
+        assert!(parse_user_input("echo >&").is_err());
+    }
+}
@@ -1,19 +1,33 @@
-use std::{process, env, io, path, time};
+use std::{process, env, ffi, io, path, time};
+use std::collections::HashMap;
 use std::error::Error;
-use std::io::{Write};
+use std::rc::Rc;
+use std::io::{Write, IsTerminal};
 
 use crossterm::{self, QueueableCommand, cursor, terminal, event};
-use crossterm::style::Stylize;
+use crossterm::style::{ContentStyle, StyledContent, Stylize};
 use whoami::fallible as whoami;
 use is_executable::IsExecutable;
 
+mod builtins;
+mod complete;
+mod debug;
+mod exes;
+mod git;
+mod highlight;
+mod lang;
+mod printf;
+mod prompt;
+mod which;
+
 fn main() -> process::ExitCode {
     let mut args = env::args();
     let program_name:String = args.next().expect("Program name should always be argument 0 of the program");
     match run(&program_name, args) {
-        Ok(_) => {
+        Ok(status) => {
             clean_up();
-            process::ExitCode::SUCCESS
+            // Exit codes only go up to 255 on most platforms, anything above that wraps around like it would on a posix shell
+            process::ExitCode::from((status & 0xff) as u8)
         },
         Err(e) => {
             clean_up();
@@ -42,38 +56,134 @@ fn clean_up() {
     };
 }
 
-#[allow(dead_code)]
-enum CmdChain {
-    And(process::Command),
-    Pipe(process::Command),
+/// Everything a command might need to know about or change in the shell, shared between the interactive
+/// prompt and the non-interactive script runner.
+struct Shell {
+    program_name: String,
+    interactive: bool,
+    env_exes: exes::ExeIndex,
+    dir_path: path::PathBuf,
+    dir_name: String,
+    git_status: Option<git::GitStatus>,
+    /// What the repo looked like when `git_status` was computed, to know when it's worth computing again
+    git_stamp: Option<git::RepoStamp>,
+    /// Whether a program ran since the last prompt refresh, those can change anything under our feet
+    spawned_since_refresh: bool,
+    should_quit: bool,
+    last_status: i32,
+    /// How long the last spawned program took, None when the last command was a builtin
+    last_duration: Option<time::Duration>,
+    /// Variables set with `NAME=value` that aren't in the environment already, these are not exported to child processes
+    vars: HashMap<String, String>,
+    functions: HashMap<String, Rc<lang::Node>>,
+    /// `$0`, the script being run or the shell itself
+    script_name: String,
+    /// `$1`, `$2`, ... of the script or function currently running
+    positional: Vec<String>,
+    /// Commands and paths used this session, keyed by name or absolute path, for ranking suggestions
+    usage: HashMap<String, complete::Usage>,
+    /// How the arguments of each command complete, see the `complete` builtin
+    completions: HashMap<String, complete::Spec>,
+    /// Lines typed this session, oldest first
+    history: Vec<String>,
+    /// The last thing a completion spec's command printed, so it doesn't run on every key
+    spec_output: Option<complete::SpecOutput>,
+    /// Where commands read and write right now, changed while running pipelines and redirections
+    io: builtins::Io,
+    /// Programs started by a pipeline that's still running, they get waited on once it's done
+    pipeline_children: Vec<process::Child>,
+    /// Set while a pipeline starts its programs, those get waited on with the rest of `pipeline_children`
+    defer_wait: bool,
 }
 
-struct CmdReq {
-    start: process::Command,
-    chain: Option<Vec<CmdChain>>,
+/// Where the commands to run are coming from
+enum InputSource {
+    Interactive,
+    Command(String, Vec<String>),
+    Script(path::PathBuf, Vec<String>),
+    Stdin,
 }
 
-fn run(program_name: &str, _args: env::Args) -> Result<(), String> {
-    // Environment
-    let mut stdout = io::stdout();
-    let mut stderr = io::stderr();
-    let env_exes   = match query_env_exes() {
-        Ok(list) => list,
-        Err(_) => Vec::new(),
+fn usage(program_name: &str) -> String {
+    return format!("Usage: {} [-c <command> [args...] | <script-file> [args...]]", program_name);
+}
+
+fn parse_args(program_name: &str, mut args: env::Args) -> Result<InputSource, String> {
+    let source = match args.next() {
+        None => {
+            if io::stdin().is_terminal() {
+                InputSource::Interactive
+            } else {
+                InputSource::Stdin
+            }
+        },
+        Some(arg) => {
+            if arg == "-c" {
+                match args.next() {
+                    Some(cmd) => InputSource::Command(cmd, args.by_ref().collect()),
+                    None => return Err(format!("Option -c requires a command\n{}", usage(program_name))),
+                }
+            } else if arg == "-" {
+                InputSource::Stdin
+            } else if arg.starts_with('-') {
+                return Err(format!("Unknown option {}\n{}", arg, usage(program_name)));
+            } else {
+                InputSource::Script(path::PathBuf::from(arg), args.by_ref().collect())
+            }
+        },
     };
-    let env_cmds = query_env_cmds();
-    
-    // Setup base data
-    let mut should_quit = false;
-    let quit_commands = ["kys", "exit", "quite", "q", "kindness"];
-    // for qc in quit_commands.iter() {
-    //     env_cmds.push(qc.to_string());
-    // }
-    let (mut dir_path, mut dir_name) = query_current_directory_name()?;
-    let mut git_branch_name = match query_git_branch_name() {
-        Ok(Some(name)) => name,
-        _ => String::new(),
+    if let Some(arg) = args.next() {
+        return Err(format!("Unexpected argument {}\n{}", arg, usage(program_name)));
+    }
+    return Ok(source);
+}
+
+fn run(program_name: &str, args: env::Args) -> Result<i32, String> {
+    let source = parse_args(program_name, args)?;
+    let mut shell = Shell::new(program_name, matches!(source, InputSource::Interactive))?;
+
+    match source {
+        InputSource::Interactive => run_interactive(&mut shell)?,
+        InputSource::Command(cmd, args) => {
+            shell.positional = args;
+            shell.run_source(&cmd)?;
+        },
+        InputSource::Script(file_path, args) => {
+            let content = match std::fs::read_to_string(&file_path) {
+                Ok(x) => x,
+                Err(err) => return Err(format!("Failed to read script {}: {}", file_path.display(), err)),
+            };
+            shell.script_name = file_path.display().to_string();
+            shell.positional = args;
+            shell.run_source(&content)?;
+        },
+        InputSource::Stdin => {
+            // A line at a time so `tail -f cmds | xeorvi` runs each command as soon as it's all there, lines
+            // pile up only while an `if` or a quote is still open
+            let mut pending = String::new();
+            while !shell.should_quit {
+                let mut line = String::new();
+                if io::stdin().read_line(&mut line).iu()? == 0 {
+                    break;
+                }
+                pending.push_str(&line);
+                if lang::is_incomplete(&pending) {
+                    continue;
+                }
+                shell.run_source(&std::mem::take(&mut pending))?;
+            }
+            // Whatever was left open gets its error now
+            if !pending.is_empty() && !shell.should_quit {
+                shell.run_source(&pending)?;
+            }
+        },
     };
+
+    return Ok(shell.last_status);
+}
+
+fn run_interactive(shell: &mut Shell) -> Result<(), String> {
+    let mut stdout = io::stdout();
     let username = match query_username() {
         Ok(x) => x,
         Err(e) => {
@@ -81,13 +191,18 @@ fn run(program_name: &str, _args: env::Args) -> Result<(), String> {
             String::from("anon")
         },
     };
+    let hostname = whoami::hostname().unwrap_or_default();
+
+    complete::load_builtin_specs(shell);
+    load_rc_file(shell)?;
+    shell.refresh_prompt_state(true)?;
 
     // Setup environment data
     let (mut cols, mut rows) = terminal::size().iu()?;
     stdout.uqueue(cursor::MoveTo(0, 0))?;
     stdout.clear_term()?;
-    
-    while !should_quit {
+
+    while !shell.should_quit {
         stdout.uflush()?;
 
         // Add extra lines when at the bottom of the terminal to make space for the "prompt"
@@ -96,290 +211,590 @@ fn run(program_name: &str, _args: env::Args) -> Result<(), String> {
             stdout.ubwrite("\n\n\n\n")?;
             stdout.uqueue(cursor::MoveUp(3))?;
         }
+        let info = shell.prompt_info(&username, &hostname);
         // TODO: Move this to the handle_user_input function and redraw when user resizes window
-        let top_bar_len = cols-(dir_name.chars().count()as u16)-4;
-        stdout.uswrite(format!("╔┈{}/┈{:═<w$}", dir_name, "", w=top_bar_len as usize).cyan().on_black())?;
+        let top_template = shell.lookup_var("XEORVI_PROMPT_TOP").unwrap_or(prompt::DEFAULT_TOP_TEMPLATE.to_string());
+        for piece in prompt::render(&top_template, &info, cols) {
+            stdout.uswrite(piece)?;
+        }
         stdout.uqueue(cursor::MoveDown(1))?;
         stdout.uqueue(cursor::MoveToColumn(0))?;
         // Activate raw mode temporarily to read the user input by hand a character at a time
-        let (line, close_requested) = handle_user_input(
-            &mut stdout,
-            &username,
-            &git_branch_name,
-            &mut cols,
-            &mut rows,
-            &env_exes.iter().map(|(_, name)| name.clone()).collect(),
-            &env_cmds.iter().map(|(name, _)| name.clone()).collect(),
-        )?;
-        
+        let (line, close_requested) = handle_user_input(&mut stdout, shell, &info, &mut cols, &mut rows)?;
+
         // Don't overlap with the design thingy
         stdout.ubwrite("\n")?;
-        
+
         if close_requested {
             stdout.ubwrite("\n")?;
             stdout.uflush()?;
-            should_quit = true;
+            shell.should_quit = true;
             continue;
         }
 
-        if line.is_empty() {
-            continue;
+        if !line.trim().is_empty() {
+            shell.history.push(line.clone());
         }
-        
-        if quit_commands.contains(&line.trim().to_lowercase().as_str()) {
-            should_quit = true;
-            continue;
+        shell.run_source(&line)?;
+        shell.refresh_prompt_state(false)?;
+    }
+
+    return Ok(());
+}
+
+/// Runs `$XEORVI_RC` or `~/.xeorvirc` if there's one, the place to set `XEORVI_PROMPT` and friends
+fn load_rc_file(shell: &mut Shell) -> Result<(), String> {
+    let rc_path = match env::var_os("XEORVI_RC") {
+        Some(p) => path::PathBuf::from(p),
+        None => match env::var_os("HOME").or_else(|| env::var_os("USERPROFILE")) {
+            Some(home) => path::PathBuf::from(home).join(".xeorvirc"),
+            None => return Ok(()),
+        },
+    };
+    let content = match std::fs::read_to_string(&rc_path) {
+        Ok(x) => x,
+        Err(err) => {
+            if err.kind() != io::ErrorKind::NotFound {
+                eprintln!("[ERROR] Failed to read {}: {}", rc_path.display(), err);
+            }
+            return Ok(());
+        },
+    };
+    return shell.run_source(&content);
+}
+
+impl Shell {
+    fn new(program_name: &str, interactive: bool) -> Result<Self, String> {
+        // No shell variables yet, only the environment gets a say
+        let case = env::var(CASE_SETTING).ok().and_then(|x| exes::NameCase::from_setting(&x)).unwrap_or(exes::NameCase::platform());
+        let env_exes = exes::ExeIndex::build(case);
+        let (dir_path, dir_name) = query_current_directory_name()?;
+        return Ok(Shell {
+            program_name: program_name.to_string(),
+            interactive,
+            env_exes,
+            dir_path,
+            dir_name,
+            // Only the prompt cares about this, filled in once we know we're interactive
+            git_status: None,
+            git_stamp: None,
+            spawned_since_refresh: false,
+            should_quit: false,
+            last_status: 0,
+            last_duration: None,
+            vars: HashMap::new(),
+            functions: HashMap::new(),
+            script_name: program_name.to_string(),
+            positional: Vec::new(),
+            usage: HashMap::new(),
+            completions: HashMap::new(),
+            history: Vec::new(),
+            spec_output: None,
+            io: builtins::Io::default(),
+            pipeline_children: Vec::new(),
+            defer_wait: false,
+        });
+    }
+
+    fn query_git_status(&self) -> Option<git::GitStatus> {
+        let budget = self.lookup_var("XEORVI_GIT_BUDGET")
+            .and_then(|x| x.trim().parse::<f64>().ok())
+            .filter(|x| x.is_finite() && *x >= 0.0)
+            .unwrap_or(DEFAULT_GIT_BUDGET_SECS);
+        return git::query_git_status(&self.dir_path, time::Duration::from_secs_f64(budget));
+    }
+
+    /// Brings everything the prompt shows up to date, called after every command. Skips asking git again
+    /// when nothing could have changed, unless `force`d to.
+    fn refresh_prompt_state(&mut self, force: bool) -> Result<(), String> {
+        // Somebody deleted the directory we're in, move to the closest one that still exists
+        if !self.dir_path.is_dir() {
+            let fallback = self.dir_path.ancestors().find(|p| p.is_dir()).map(|p| p.to_path_buf());
+            if let Some(fallback) = fallback {
+                let mut stderr = io::stderr();
+                stderr.uswrite("[ERROR]".red())?;
+                stderr.ubwrite(format!(" {} no longer exists, moving to {}\n", self.dir_path.display(), fallback.display()))?;
+                if env::set_current_dir(&fallback).is_ok() {
+                    self.dir_path = fallback;
+                }
+            }
+        }
+        if let Some(x) = self.dir_path.file_name() {
+            self.dir_name = x.to_string_lossy().to_string();
+        }
+
+        // Whatever ran might have installed or removed programs, this only rescans when a PATH directory changed
+        let case = self.name_case();
+        self.env_exes.refresh(case);
+
+        let stamp = git::query_repo_stamp(&self.dir_path);
+        if force || self.spawned_since_refresh || stamp != self.git_stamp {
+            self.git_status = match stamp {
+                Some(_) => self.query_git_status(),
+                None => None,
+            };
+            self.git_stamp = stamp;
         }
+        self.spawned_since_refresh = false;
+        return Ok(());
+    }
+
+    /// Whether `Git` and `git` are the same command, `XEORVI_CASE_SENSITIVE` overrides what the platform does
+    fn name_case(&self) -> exes::NameCase {
+        return self.lookup_var(CASE_SETTING)
+            .and_then(|x| exes::NameCase::from_setting(&x))
+            .unwrap_or(exes::NameCase::platform());
+    }
+
+    /// PATH as a command sees it, a `PATH=...` in front of it included
+    fn command_path_var(&self, env_vars: &[(String, String)]) -> Option<ffi::OsString> {
+        return env_vars.iter().rev().find(|(k, _)| k.eq_ignore_ascii_case("path")).map(|(_, v)| v.into()).or_else(|| env::var_os("PATH"));
+    }
 
-        let req = match parse_user_input(line) {
+    fn prompt_info(&self, username: &str, hostname: &str) -> prompt::PromptInfo {
+        // Only worth showing how long the last command took when it was long enough for the user to notice
+        let duration_threshold = self.lookup_var("XEORVI_DURATION_THRESHOLD")
+            .and_then(|x| x.trim().parse::<f64>().ok())
+            .unwrap_or(DEFAULT_DURATION_THRESHOLD_SECS);
+        return prompt::PromptInfo {
+            cwd: self.dir_name.clone(),
+            path: self.dir_path.display().to_string(),
+            user: username.to_string(),
+            host: hostname.to_string(),
+            git: self.git_status.as_ref().map(|x| x.summary()).unwrap_or_default(),
+            git_commit: self.git_status.as_ref().and_then(|x| x.oid.as_ref()).map(|x| x.chars().take(7).collect()).unwrap_or_default(),
+            status: self.last_status,
+            duration: self.last_duration.filter(|d| d.as_secs_f64() >= duration_threshold).map(format_duration),
+            // No job control, yet!
+            jobs: 0,
+        };
+    }
+
+    /// Parses and runs a chunk of input, a line typed at the prompt or a whole script. Only fatal errors
+    /// (i.e. we can't write to the terminal anymore) are returned as `Err`, everything else gets reported
+    /// and the shell keeps going.
+    fn run_source(&mut self, input: &str) -> Result<(), String> {
+        let block = match lang::parse_user_input(input) {
             Ok(x) => x,
             Err(err) => {
+                let mut stderr = io::stderr();
                 stderr.uswrite("[uERROR]".red())?;
                 stderr.uswrite(format!(" {}\n", err))?;
-                continue;
+                // Same status bash uses for syntax errors
+                self.last_status = 2;
+                return Ok(());
             },
         };
-        let uprog_name = req.start.get_program().to_string_lossy().to_string();
-        if req.chain.is_some() {
-            stderr.uswrite("[tERROR]".red())?;
-            stderr.uswrite(" Chaining/piping commands is not supported, yet!\n")?;
-            continue;
-        }
+        return self.exec_block(&block);
+    }
 
-        if uprog_name.to_lowercase() == "cd" || uprog_name.to_lowercase() == "chdir" {
-            let uargs:Vec<_> = req.start.get_args().collect();
-            if let Some(path) = uargs.get(0) {
-                match parse_path(&dir_path, &path.to_string_lossy().to_string()) {
-                    Err(err) => {
-                        stderr.uswrite("[?ERROR]".red())?;
-                        stderr.ubwrite(format!(" {}", err))?;
-                    },
-                    Ok(path) => {
-                        match env::set_current_dir(&path) {
-                            Ok(_) => dir_path = path,
-                            Err(err) => {
-                                stderr.uswrite("[sERROR]".red())?;
-                                stderr.ubwrite(format!(" Failed to switch dir: {}\n", err))?;
-                            },
-                        };
-                        match dir_path.file_name() {
-                            Some(x) => dir_name = x.to_string_lossy().to_string(),
-                            None => {
-                                stderr.uswrite("[ERROR]".red())?;
-                                stderr.ubwrite(" Rust failed to get directory name separated\n")?;
-                            },
-                        };
-                        git_branch_name = match query_git_branch_name() {
-                            Ok(Some(name)) => name,
-                            _ => String::new(),
-                        };
-                    },
-                };
+    /// Runs a builtin or spawns a program, `argv` has already gone through expansion
+    fn run_command(&mut self, argv: Vec<String>, env_vars: Vec<(String, String)>) -> Result<(), String> {
+        let mut stdout = io::stdout();
+        let mut stderr = io::stderr();
+        self.last_duration = None;
+
+        let uprog_name = argv[0].clone();
+        if let Some(builtin) = builtins::find(&uprog_name, self.name_case()) {
+            let mut io = self.io.try_clone()?;
+            let res = if argv.get(1).is_some_and(|x| x == "--help") {
+                builtins::write_help(&mut io.stdout, builtin).map(|_| 0)
             } else {
-                stdout.uswrite(format!("{}\n", dir_path.display()))?;
-            }
-            continue;
+                builtin.run(self, &argv, &env_vars, &mut io)
+            };
+            let res = res.and_then(|status| io.stdout.uflush().map(|_| status));
+            self.last_status = match res {
+                Ok(x) => x,
+                // Nobody's reading anymore, same as a program getting SIGPIPE'd there's nothing worth saying
+                Err(_) if io.stdout.is_closed() => 1,
+                // A full disk or a closed pipe on the other end is the command failing, not the shell. Losing
+                // the terminal is still fatal, that one's on us to notice.
+                Err(err) if !io.stdout.is_terminal() || !io.stderr.is_terminal() => {
+                    stderr.uswrite("[ERROR]".red())?;
+                    stderr.ubwrite(format!(" {}: {}\n", uprog_name, err))?;
+                    1
+                },
+                Err(err) => return Err(err),
+            };
+            return Ok(());
         }
 
-        if cfg!(debug_assertions) {
-            if uprog_name.to_lowercase() == "print-env" {
-                stdout.ubwrite("Commands inherited env vars:\n")?;
-                let env_vars:Vec<_> = req.start.get_envs().collect();
-                if env_vars.is_empty() {
-                    stdout.ubwrite(":> None\n")?;
-                }
-                for (vname_os, maybe_vvalue_os) in env_vars.iter() {
-                    stdout.ubwrite(":> ")?;
-                    stdout.uswrite(format!("{:?} -> {:?}\n", vname_os, maybe_vvalue_os))?;
-                }
-                stdout.uswrite(format!("{} env vars:\n", program_name))?;
-                let env_vars:Vec<_> = env::vars_os().collect();
-                if env_vars.is_empty() {
-                    stdout.ubwrite(":> None\n")?;
-                }
-                for (key, val) in env::vars_os() {
-                    stdout.ubwrite(":> ")?;
-                    stdout.uswrite(format!("{:?} -> {:?}\n", key, val))?;
-                }
-                continue;
-            }
+        // Scripts can change the case policy halfway through, the index has to go by the new one
+        let case = self.name_case();
+        if self.env_exes.case() != case {
+            self.env_exes = exes::ExeIndex::build(case);
         }
 
-        if cfg!(debug_assertions) {
-            if uprog_name.to_lowercase() == "print-exes" {
-                stdout.ubwrite(format!("Known executable files({}):\n", env_exes.len()))?;
-                for (path, name) in env_exes.iter() {
-                    stdout.ubwrite(format!(" - {}\n   @ {}\n", name, path.display()))?;
-                }
-                stdout.ubwrite("\n")?;
-                continue;
-            }
-        }
-        if cfg!(debug_assertions) {
-            if uprog_name.to_lowercase() == "print-pp" {
-                for (k, v) in std::env::vars_os() {
-                    if k.to_string_lossy().to_string().to_lowercase() != "path" {
-                        continue;
-                    }
-                    for path_str in v.to_string_lossy().to_string().split(if cfg!(windows) { ';' } else { ':' }) {
-                        if path_str.is_empty() {
-                            continue;
-                        }
-                        let path = path::Path::new(path_str);
-                        stdout.ubwrite(format!("- {}\n", path_str))?;
-                        if ! path.exists() {
-                            stdout.ubwrite(" - Exists: false\n")?;
-                            continue;
-                        }
-                        stdout.ubwrite(" - Exists: true\n")?;
-                        if ! path.is_dir() {
-                            stdout.ubwrite(" - IsFolder: false\n")?;
-                            continue;
-                        }
-                        stdout.ubwrite(" - IsFolder: true\n")?;
-                        if let Ok(entries) = path.read_dir() {
-                            stdout.ubwrite(" - CanRead: true\n")?;
-                            for entry_res in entries {
-                                match entry_res {
-                                    Ok(entry) => {
-                                        stdout.ubwrite(format!(" - EntryPath: {}\n", entry.path().display()))?;
-                                        let entry_path = entry.path();
-                                        if !entry_path.is_file() {
-                                            stdout.ubwrite(" - Entry.IsFile: false\n")?;
-                                            continue;
-                                        }
-                                        stdout.ubwrite(" - Entry.IsFile: true\n")?;
-                                        stdout.ubwrite(format!(" - Entry.IsExe: {}\n", entry_path.is_executable()))?;
-                                    },
-                                    Err(err) => {
-                                        stdout.ubwrite(format!(" - EntryError: {}\n", err))?;
-                                    },
-                                };
-                            }
-                        } else {
-                            stdout.ubwrite(" - CanRead: false\n")?;
-                        }
-                    }
-                }
-                continue;
+        let mut req = process::Command::new(&uprog_name);
+        req.args(&argv[1..]);
+        req.envs(env_vars.iter().map(|(k, v)| (k, v)));
+
+        // Windows only finds .exe files by itself, anything else in PATHEXT has to be looked up by hand
+        // and scripts need to be handed to whatever runs them
+        if *self.env_exes.rule() != exes::NameRule::Unix && !uprog_name.contains(['/', '\\']) {
+            if let Some(exe) = self.command_path_var(&env_vars).and_then(|x| exes::resolve(&x, &uprog_name, self.env_exes.rule())) {
+                let (program, prefix) = exes::spawn_parts(&exe.path);
+                req = process::Command::new(program);
+                req.args(prefix).args(&argv[1..]).envs(env_vars.iter().map(|(k, v)| (k, v)));
             }
         }
 
-        if uprog_name.to_lowercase() == "echo" {
-            let mut first = true;
-            for uarg in req.start.get_args().map(|x| x.to_string_lossy()) {
-                if first {
-                    stdout.uswrite(uarg)?;
-                    first = false;
-                } else {
-                    stdout.uswrite(format!(" {}", uarg))?;
-                }
+        // The OS only finds the exact name, `GIT` has to be turned into the `git` it means by hand
+        if *self.env_exes.rule() == exes::NameRule::Unix && case == exes::NameCase::Insensitive && !uprog_name.contains('/') {
+            if let Some(exe) = self.env_exes.get(&uprog_name) {
+                req = process::Command::new(&exe.path);
+                req.args(&argv[1..]).envs(env_vars.iter().map(|(k, v)| (k, v)));
             }
-            stdout.ubwrite("\n")?;
-            continue;
         }
 
-        if uprog_name.to_lowercase() == "cls" || uprog_name.to_lowercase() == "clear" {
-            stdout.clear_term()?;
-            stdout.uqueue(cursor::MoveTo(0, 0))?;
-            continue;
-        }
+        // Paths are relative to where the shell thinks it is, don't leave that up to the OS
+        let program_path = if uprog_name.contains(['/', '\\']) {
+            let program_path = match parse_path(&self.dir_path, &uprog_name) {
+                Ok(x) if x.exists() => x,
+                _ => {
+                    self.last_status = 127;
+                    stderr.ubwrite(format!("xeorvi: no such file or directory: {}\n", uprog_name))?;
+                    return Ok(());
+                },
+            };
+            if program_path.is_dir() {
+                self.last_status = 126;
+                stderr.ubwrite(format!("xeorvi: is a directory: {}\n", uprog_name))?;
+                return Ok(());
+            }
+            if !program_path.is_executable() {
+                self.last_status = 126;
+                stderr.ubwrite(format!("xeorvi: permission denied: {}, it exists but can't be executed\n", uprog_name))?;
+                return Ok(());
+            }
+            // Same as from PATH, on Windows a .cmd or .ps1 goes to whatever runs those
+            let (program, prefix) = if *self.env_exes.rule() != exes::NameRule::Unix {
+                exes::spawn_parts(&program_path)
+            } else {
+                (program_path.clone().into_os_string(), Vec::new())
+            };
+            req = process::Command::new(program);
+            req.args(prefix).args(&argv[1..]).envs(env_vars.iter().map(|(k, v)| (k, v)));
+            Some(program_path)
+        } else {
+            self.env_exes.get(&uprog_name).map(|x| x.path.clone())
+        };
 
-        if cfg!(debug_assertions) {
-            stdout.ubwrite(format!("[uCMD] {:?}\n", req.start))?;
+        if cfg!(debug_assertions) && self.interactive {
+            stdout.ubwrite(format!("[uCMD] {:?}\n", req))?;
         }
         stdout.uflush()?;
 
-        let mut req = req;
-        match req.start.spawn() {
+        let start = time::Instant::now();
+        self.spawned_since_refresh = true;
+        self.io.apply(&mut req)?;
+        let spawned = match (req.spawn(), &program_path) {
+            // Not something the OS knows how to run, most likely a script
+            (Err(err), Some(program_path)) if err.raw_os_error() == Some(EXEC_FORMAT_ERROR) => match script_request(program_path) {
+                Some(mut req) => {
+                    req.args(&argv[1..]).envs(env_vars.iter().map(|(k, v)| (k, v)));
+                    self.io.apply(&mut req)?;
+                    req.spawn()
+                },
+                None => Err(err),
+            },
+            (spawned, _) => spawned,
+        };
+        match spawned {
+            // Whatever reads our output has to be running at the same time, the pipeline waits for it once it's done
+            Ok(child) if self.defer_wait || self.io.stdout.is_pipe() => {
+                self.pipeline_children.push(child);
+                self.last_status = 0;
+            },
             Ok(mut child) => match child.wait() {
                 Ok(status) => {
-                    if !status.success() {
+                    self.last_duration = Some(start.elapsed());
+                    self.last_status = exit_status_code(&status);
+                    if !status.success() && self.interactive {
                         match status.code() {
                             Some(code) => stdout.uswrite(format!("t :: exit code was {}\n", code))?,
-                            None => stdout.uswrite(format!("t :: program closed by signal\n"))?,
+                            None => stdout.uswrite("t :: program closed by signal\n")?,
                         };
                     }
                 },
                 Err(err) => {
+                    self.last_status = 1;
                     stderr.uswrite("[cERROR]".red())?;
                     stderr.ubwrite(format!(" {}\n", err))?;
                 },
             },
+            Err(err) if err.raw_os_error() == Some(EXEC_FORMAT_ERROR) => {
+                self.last_status = 126;
+                stderr.ubwrite(format!("xeorvi: exec format error: {}, it's neither a program nor a script\n", uprog_name))?;
+            },
+            // The file is right there, it's what its shebang points at that's missing
+            Err(err) if err.kind() == io::ErrorKind::NotFound && program_path.is_some() => {
+                self.last_status = 126;
+                match program_path.as_deref().map(read_shebang).unwrap_or_default().first() {
+                    Some(interpreter) => stderr.ubwrite(format!("xeorvi: bad interpreter: {} doesn't exist, {} needs it to run\n", interpreter, uprog_name))?,
+                    None => stderr.ubwrite(format!("xeorvi: bad interpreter: whatever runs {} doesn't exist\n", uprog_name))?,
+                };
+            },
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                self.last_status = 127;
+                stderr.ubwrite(format!("xeorvi: command not found: {}\n", uprog_name))?;
+                let close = complete::did_you_mean(self, &uprog_name);
+                if !close.is_empty() {
+                    stderr.uswrite("  did you mean: ".dim())?;
+                    stderr.ubwrite(format!("{}\n", close.join(", ")))?;
+                }
+            },
+            Err(err) if err.kind() == io::ErrorKind::PermissionDenied => {
+                self.last_status = 126;
+                stderr.ubwrite(format!("xeorvi: permission denied: {}, it exists but can't be executed\n", uprog_name))?;
+            },
             Err(err) => {
+                self.last_status = 1;
                 stderr.uswrite("[cERROR]".red())?;
                 stderr.ubwrite(format!(" {}\n", err))?;
             },
         };
+
+        return Ok(());
     }
+}
+
+#[cfg(unix)]
+const EXEC_FORMAT_ERROR: i32 = libc::ENOEXEC;
+// ERROR_BAD_EXE_FORMAT, what Windows says about anything that isn't a .exe
+#[cfg(not(unix))]
+const EXEC_FORMAT_ERROR: i32 = 193;
 
-    Ok(())
+/// The interpreter and arguments in a script's `#!` line, empty when it doesn't have one
+fn read_shebang(path: &path::Path) -> Vec<String> {
+    let mut first_line = String::new();
+    if let Ok(file) = std::fs::File::open(path) {
+        let _ = io::BufRead::read_line(&mut io::BufReader::new(io::Read::take(file, 512)), &mut first_line);
+    }
+    return match first_line.strip_prefix("#!") {
+        Some(x) => x.split_whitespace().map(String::from).collect(),
+        None => Vec::new(),
+    };
 }
 
+/// Whether the start of the file looks like text, a script rather than some binary
+fn is_text_file(path: &path::Path) -> bool {
+    let mut buf = Vec::new();
+    match std::fs::File::open(path) {
+        Ok(file) => if io::Read::read_to_end(&mut io::Read::take(file, 512), &mut buf).is_err() {
+            return false;
+        },
+        Err(_) => return false,
+    };
+    if buf.contains(&0) {
+        return false;
+    }
+    // The 512 bytes might end halfway through a character, that's still text
+    return match std::str::from_utf8(&buf) {
+        Ok(_) => true,
+        Err(err) => err.error_len().is_none(),
+    };
+}
+
+/// What to run a script with when the OS can't: the interpreter in its shebang line, or else xeorvi itself.
+/// None when it's not a script at all, or one of the kinds something else is meant to run.
+fn script_request(path: &path::Path) -> Option<process::Command> {
+    let mut words = read_shebang(path);
+    if words.is_empty() {
+        let (program, _) = exes::spawn_parts(path);
+        if program != path.as_os_str() || !is_text_file(path) {
+            return None;
+        }
+        let xeorvi = env::current_exe().unwrap_or(path::PathBuf::from("xeorvi"));
+        let mut req = process::Command::new(xeorvi);
+        req.arg(path);
+        return Some(req);
+    }
+    let mut interpreter = words.remove(0);
+    // Shebangs are written for unix, on Windows `/bin/bash` is just `bash` somewhere in PATH
+    if !path::Path::new(&interpreter).exists() {
+        interpreter = path::Path::new(&interpreter).file_name().map(|x| x.to_string_lossy().to_string()).unwrap_or(interpreter);
+    }
+    // `#!/usr/bin/env bash` means bash
+    if interpreter == "env" && !words.is_empty() {
+        interpreter = words.remove(0);
+    }
+    let mut req = process::Command::new(interpreter);
+    req.args(words).arg(path);
+    return Some(req);
+}
+
+/// User and system CPU time used by all the children we've waited on so far
+#[cfg(unix)]
+fn query_children_cpu_time() -> Option<(time::Duration, time::Duration)> {
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    // SAFETY: usage is a valid, writable rusage struct for getrusage to fill in
+    if unsafe { libc::getrusage(libc::RUSAGE_CHILDREN, &mut usage) } != 0 {
+        return None;
+    }
+    let to_duration = |tv: libc::timeval| time::Duration::from_secs(tv.tv_sec as u64) + time::Duration::from_micros(tv.tv_usec as u64);
+    return Some((to_duration(usage.ru_utime), to_duration(usage.ru_stime)));
+}
+
+// Windows can tell you this too but only per process handle and std doesn't hand those out, only wall time for now
+#[cfg(not(unix))]
+fn query_children_cpu_time() -> Option<(time::Duration, time::Duration)> {
+    return None;
+}
+
+/// Formats like bash's `time` does, i.e. `0m1.234s`
+fn format_time_report(d: time::Duration) -> String {
+    let secs = d.as_secs_f64();
+    let mins = (secs / 60.0).floor();
+    return format!("{}m{:.3}s", mins as u64, secs - mins * 60.0);
+}
+
+/// Short human readable duration for the prompt, i.e. `850ms`, `3.2s`, `1m05s` or `2h10m`
+fn format_duration(d: time::Duration) -> String {
+    let secs = d.as_secs();
+    if secs < 1 {
+        return format!("{}ms", d.as_millis());
+    }
+    if secs < 60 {
+        return format!("{:.1}s", d.as_secs_f64());
+    }
+    if secs < 60 * 60 {
+        return format!("{}m{:02}s", secs / 60, secs % 60);
+    }
+    return format!("{}h{:02}m", secs / (60 * 60), (secs / 60) % 60);
+}
+
+/// Turn a child's exit status into the number a posix shell would report, signals are reported as 128+signal
+fn exit_status_code(status: &process::ExitStatus) -> i32 {
+    if let Some(code) = status.code() {
+        return code;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+    return 1;
+}
+
+/// Why `timed_output` came back without any output
+enum TimedFailure {
+    /// Couldn't even start it, most likely it's not installed or not on the PATH
+    Spawn,
+    Failed,
+    TimedOut,
+}
+
+/// Runs `req` with stdout captured and gives up on it once `budget` runs out, for the stuff that runs while
+/// somebody is waiting on the prompt. Only successful runs count, stdin and stderr go nowhere.
+fn timed_output(req: &mut process::Command, budget: time::Duration) -> Result<Vec<u8>, TimedFailure> {
+    let mut child = match req.stdin(process::Stdio::null()).stdout(process::Stdio::piped()).stderr(process::Stdio::null()).spawn() {
+        Ok(x) => x,
+        Err(_) => return Err(TimedFailure::Spawn),
+    };
+    let mut stdout = match child.stdout.take() {
+        Some(x) => x,
+        None => return Err(TimedFailure::Spawn),
+    };
+    // Read on another thread, otherwise a chatty program fills up the pipe and never exits
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        let res = io::Read::read_to_end(&mut stdout, &mut buf).map(|_| buf);
+        let _ = tx.send(res);
+    });
+    match rx.recv_timeout(budget) {
+        Ok(Ok(buf)) => {
+            match child.wait() {
+                Ok(status) if status.success() => return Ok(buf),
+                _ => return Err(TimedFailure::Failed),
+            };
+        },
+        Ok(Err(_)) => {
+            let _ = child.wait();
+            return Err(TimedFailure::Failed);
+        },
+        Err(_) => {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(TimedFailure::TimedOut);
+        },
+    };
+}
+
+
+/// Seconds the prompt is willing to wait on `git status`, override with `XEORVI_GIT_BUDGET`
+const DEFAULT_GIT_BUDGET_SECS: f64 = 0.2;
+
+/// Seconds a command has to take before the prompt shows its duration, override with `XEORVI_DURATION_THRESHOLD`
+const DEFAULT_DURATION_THRESHOLD_SECS: f64 = 2.0;
+/// Set to yes or no to say whether command names are case sensitive, see `exes::NameCase`
+const CASE_SETTING: &str = "XEORVI_CASE_SENSITIVE";
 
 fn handle_user_input(
     stdout: &mut io::Stdout,
-    username: &str,
-    git_branch_name: &str,
+    shell: &mut Shell,
+    info: &prompt::PromptInfo,
     cols: &mut u16,
     rows: &mut u16,
-    env_exes: &Vec<String>,
-    env_cmds: &Vec<String>
 ) -> Result<(String, bool), String> {
+    let line_template = shell.lookup_var("XEORVI_PROMPT").unwrap_or(prompt::DEFAULT_LINE_TEMPLATE.to_string());
     terminal::enable_raw_mode().iu()?;
     
     let mut buf = String::new();
-    let mut sgs = Vec::new();
-    // TODO: Also include current directory stuff into suggestions
-    let draw_line = move |stdout: &mut io::Stdout, cols: u16, usr_txt: &str, suggestions: &Vec<String>, active_suggestion_index: usize| -> Result<(), String> {
+    // `painted` is `usr_txt` with its colours, `ghost` is drawn greyed out after it and `menu` is the selected suggestion when the menu is open
+    let draw_line = move |stdout: &mut io::Stdout, cols: u16, rows: u16, usr_txt: &str, painted: &[StyledContent<String>], ghost: &str, suggestions: &[complete::Candidate], menu: Option<usize>| -> Result<(), String> {
+        let layout = menu.map(|_| MenuLayout::new(suggestions, cols, rows));
+        if let Some(layout) = &layout {
+            // Scroll the prompt up when the menu wouldn't fit under it
+            let (_, y) = cursor::position().iu()?;
+            let needed = (y as usize + 2 + layout.visible_rows).saturating_sub(rows as usize) as u16;
+            if needed > 0 {
+                stdout.uqueue(terminal::ScrollUp(needed))?;
+                stdout.uqueue(cursor::MoveUp(needed))?;
+            }
+        }
         stdout.uqueue(cursor::MoveToColumn(0))?;
         stdout.uqueue(terminal::Clear(terminal::ClearType::CurrentLine))?;
-        stdout.uswrite("╠┈".cyan().on_black())?;
-        stdout.uswrite(format!("«{}»", username).white().on_black())?;
-        if !git_branch_name.is_empty() {
-            stdout.uswrite("┈Git(".red().on_black())?;
-            stdout.uswrite(git_branch_name.white().on_black())?;
-            stdout.uswrite(")".red().on_black())?;
+        for piece in prompt::render(&line_template, info, cols) {
+            stdout.uswrite(piece)?;
+        }
+        for piece in painted.iter() {
+            stdout.uswrite(piece)?;
         }
-        stdout.ubwrite("∑◈ ")?;
-        stdout.ubwrite(usr_txt)?;
         stdout.uqueue(cursor::SavePosition)?;
+        stdout.uswrite(ghost.dark_grey())?;
         stdout.uqueue(cursor::MoveDown(1))?;
         stdout.uqueue(cursor::MoveToColumn(0))?;
-        stdout.uqueue(terminal::Clear(terminal::ClearType::CurrentLine))?;
+        // Takes whatever menu was drawn last time with it
+        stdout.uqueue(terminal::Clear(terminal::ClearType::FromCursorDown))?;
         stdout.uswrite("╚═══════╝".cyan().on_black())?;
-        
-        if usr_txt.len() < 1 || suggestions.is_empty() {
+
+        if let (Some(layout), Some(selected)) = (layout, menu) {
+            draw_completion_menu(stdout, &layout, suggestions, selected)?;
+        } else if usr_txt.is_empty() || suggestions.is_empty() {
             stdout.uswrite(" {}".dim().grey())?;
         } else {
             let (x, _) = cursor::position().iu()?;
-            let mut it = suggestions.iter().skip(active_suggestion_index);
+            let mut it = suggestions.iter();
             if let Some(first) = it.next() {
                 let mut x = x;
-                if !usr_txt.starts_with(&**first) {
-                    let s = format!(" {{{}", usr_txt);
-                    x += s.chars().count() as u16;
-                    stdout.uswrite(s.yellow())?;
-                    stdout.ubwrite("|")?;
-                    x += 1;
-                    let s = first.chars().skip(usr_txt.len()).collect::<String>();
-                    x += s.chars().count() as u16 + 1u16;
-                    stdout.ubwrite(format!("{}}}", s))?;
-                } else {
-                    stdout.uswrite(" {".dim())?;
-                    x += 2;
-                    stdout.uswrite(format!("{}", first).yellow())?;
-                    x += first.chars().count() as u16;
-                    stdout.uswrite("}".dim())?;
-                    x += 2;
-                }
-                while let Some(item) = it.next() {
-                    let s = format!(" | {}", item);
-                    x += s.chars().count() as u16;
+                stdout.uswrite(" {".dim())?;
+                x += 2;
+                let plain = ContentStyle::new().yellow();
+                write_matched(stdout, first, plain, plain.bold().underlined())?;
+                x += first.text.chars().count() as u16;
+                stdout.uswrite("}".dim())?;
+                x += 1;
+                for item in it {
+                    x += item.text.chars().count() as u16 + 3;
                     if x >= cols {
                         break;
                     }
-                    stdout.uswrite(s.dim())?;
+                    stdout.uswrite(" | ".dim())?;
+                    write_matched(stdout, item, ContentStyle::new().dim(), ContentStyle::new().yellow())?;
                 }
             }
         }
@@ -388,16 +803,16 @@ fn handle_user_input(
         Ok(())
     };
 
-    for c in env_cmds.iter() {
-        sgs.push(c.clone());
-    }
-    for e in env_exes.iter() {
-        sgs.push(e.clone());
-    }
-    draw_line(stdout, *cols, &buf, &sgs, 0)?;
+    let mut completion = complete::complete(shell, &buf);
+    // Only redo the completion when the buffer changes, the loop below spins way more often than that
+    let mut completed_buf = buf.clone();
+    let mut ghost = String::new();
+    let mut painted = Vec::new();
+    let mut menu:Option<usize> = None;
+    // A Tab that couldn't add anything to the word, the next one opens the menu
+    let mut tab_stuck = false;
+    draw_line(stdout, *cols, *rows, &buf, &painted, &ghost, &completion.candidates, menu)?;
     let mut is_done = false;
-    let mut last_suggestion:Option<String> = None;
-    let mut sgs_idx = 0isize;
     while !is_done {
         if event::poll(time::Duration::ZERO).iu()? {
             match event::read().iu()? {
@@ -412,6 +827,7 @@ fn handle_user_input(
                     if event.kind != event::KeyEventKind::Press {
                         break 'key_event_block;
                     }
+                    let was_stuck = std::mem::take(&mut tab_stuck);
                     if !event.modifiers.is_empty() {
                         if event.modifiers == event::KeyModifiers::CONTROL {
                             if event.code == event::KeyCode::Backspace {
@@ -447,27 +863,65 @@ fn handle_user_input(
                             }
                             break 'key_event_block;
                         }
+                        if event.modifiers == event::KeyModifiers::ALT {
+                            if event.code == event::KeyCode::Right {
+                                // Just the next word of the suggestion, along with the spaces in front of it
+                                let spaces = ghost.len() - ghost.trim_start().len();
+                                let word = ghost[spaces..].find(char::is_whitespace).map(|x| x + spaces).unwrap_or(ghost.len());
+                                buf.push_str(&ghost[..word]);
+                            }
+                            break 'key_event_block;
+                        }
                         if event.modifiers != event::KeyModifiers::SHIFT {
                             break 'key_event_block;
                         }
                     }
+                    if let Some(selected) = menu {
+                        let sgs = &completion.candidates;
+                        let layout = MenuLayout::new(sgs, *cols, *rows);
+                        let moved = match event.code {
+                            event::KeyCode::Tab | event::KeyCode::Right => Some((selected + 1) % sgs.len()),
+                            event::KeyCode::BackTab | event::KeyCode::Left => Some((selected + sgs.len() - 1) % sgs.len()),
+                            event::KeyCode::Down => Some(if selected + layout.columns < sgs.len() { selected + layout.columns } else { selected % layout.columns }),
+                            event::KeyCode::Up => Some(if selected >= layout.columns { selected - layout.columns } else { layout.last_in_column(selected, sgs.len()) }),
+                            _ => None,
+                        };
+                        if moved.is_some() {
+                            menu = moved;
+                            break 'key_event_block;
+                        }
+                        menu = None;
+                        match event.code {
+                            event::KeyCode::Enter => {
+                                completion.apply(&mut buf, selected);
+                                break 'key_event_block;
+                            },
+                            event::KeyCode::Esc => break 'key_event_block,
+                            // Anything else closes the menu and goes on as usual
+                            _ => {},
+                        };
+                    }
                     match event.code {
                         event::KeyCode::Char(c) => { buf.push(c); },
                         event::KeyCode::Backspace => { let _ = buf.pop(); },
                         event::KeyCode::Enter => { is_done = true; },
+                        event::KeyCode::Right | event::KeyCode::End => { buf.push_str(&ghost); },
                         event::KeyCode::Tab => {
-                            if let Some(sg) = last_suggestion {
-                                buf.clear();
-                                buf.push_str(sg.as_str());
-                                buf.push(' ');
+                            if completion.candidates.len() == 1 {
+                                completion.apply(&mut buf, 0);
+                            } else if !completion.candidates.is_empty() {
+                                let prefix = completion.common_prefix();
+                                if prefix.len() > completion.range.len() {
+                                    buf.replace_range(completion.range.clone(), &prefix);
+                                    // Filling in the common part counts as the first Tab
+                                    tab_stuck = true;
+                                } else if was_stuck {
+                                    menu = Some(0);
+                                } else {
+                                    tab_stuck = true;
+                                }
                             }
                         },
-                        event::KeyCode::Right => {
-                            sgs_idx += 1;
-                        },
-                        event::KeyCode::Left => {
-                            sgs_idx -= 1;
-                        },
                         _ => {},
                     };
                 },
@@ -476,105 +930,97 @@ fn handle_user_input(
                 _ => {},
             };
         }
-        let sgs:Vec<_> = sgs.iter().filter(|name| name.starts_with(&buf)).map(|x| x.clone()).collect();
-        sgs_idx = if sgs.is_empty() || sgs_idx >= sgs.len() as isize {
-            0
-        } else if sgs_idx < 0 {
-            (sgs.len() - 1) as isize
-        } else {
-            sgs_idx
-        };
-        last_suggestion = sgs.get(sgs_idx as usize).map(|x| x.clone());
-        draw_line(stdout, *cols, &buf, &sgs, sgs_idx as usize)?;
+        if buf != completed_buf {
+            completion = complete::complete(shell, &buf);
+            completed_buf = buf.clone();
+            ghost = complete::autosuggestion(shell, &buf).unwrap_or_default();
+            painted = highlight::highlight(shell, &buf);
+            menu = None;
+        }
+        draw_line(stdout, *cols, *rows, &buf, &painted, &ghost, &completion.candidates, menu)?;
     }
-    draw_line(stdout, *cols, &buf, &Vec::new(), 0)?;
+    draw_line(stdout, *cols, *rows, &buf, &painted, "", &[], None)?;
     stdout.uqueue(cursor::MoveDown(1))?;
     terminal::disable_raw_mode().iu()?;
     return Ok((buf, false));
 }
 
 
-// TODO: Test this monstrosity
-fn parse_user_input(input: String) -> Result<CmdReq, String> {
-    let mut buffer = String::new();
-    let mut command:Option<CmdReq> = None;
-    let mut chars = input.chars();
-    let mut quotes = None;
-    while let Some(ch) = chars.next() {
-        if let Some(qch) = quotes {
-            if ch == qch {
-                quotes = None;
-                continue;
-            }
-            // TODO: Handle proper escaping
-            if ch == '\\' {
-                if let Some(nch) = chars.next() {
-                    buffer.push(nch);
-                    continue;
-                }
-            }
-            buffer.push(ch);
-            continue;
+/// How the completion menu is laid out, filled row by row so the arrows move the way they look like they would
+struct MenuLayout {
+    columns: usize,
+    column_width: usize,
+    visible_rows: usize,
+}
+
+impl MenuLayout {
+    fn new(items: &[complete::Candidate], cols: u16, rows: u16) -> Self {
+        let column_width = items.iter().map(|x| x.text.chars().count()).max().unwrap_or(0) + 2;
+        let columns = (cols as usize / column_width).max(1);
+        let total_rows = items.len().div_ceil(columns);
+        // Leave room for the prompt and the top bar
+        let visible_rows = total_rows.min((rows as usize).saturating_sub(3).max(1));
+        return MenuLayout { columns, column_width, visible_rows };
+    }
+
+    /// The bottom-most item in the same column, where going up from the first row wraps around to
+    fn last_in_column(&self, idx: usize, len: usize) -> usize {
+        let column = idx % self.columns;
+        let last_row = (len - 1) / self.columns;
+        if last_row * self.columns + column < len {
+            return last_row * self.columns + column;
         }
-        match ch {
-            '&' | '|' => return Err(format!("[TODO] Parsing {} is not supported yet", ch)),
-            '"' | '\'' => quotes = Some(ch),
-            ' ' => match command {
-                None => {
-                    command = Some(CmdReq {
-                        start: process::Command::new(&buffer),
-                        chain: None
-                    });
-                    buffer.clear();
-                },
-                Some(ref mut req) => {
-                    if let Some(ref mut chained) = req.chain {
-                        match chained.last_mut() {
-                            Some(CmdChain::And(cmd)) => cmd.arg(&buffer),
-                            Some(CmdChain::Pipe(cmd)) => cmd.arg(&buffer),
-                            _ => unreachable!(),
-                        };
-                    } else {
-                        req.start.arg(&buffer);
-                    }
-                    buffer.clear();
-                }
-            },
-            _ => buffer.push(ch),
-        };
+        return (last_row - 1) * self.columns + column;
     }
-    
-    if !buffer.is_empty() {
-        match command {
-            Some(ref mut req) => {
-                if let Some(ref mut chained) = req.chain {
-                    match chained.last_mut() {
-                        Some(CmdChain::And(ref mut cmd)) => cmd.arg(buffer),
-                        Some(CmdChain::Pipe(ref mut cmd)) => cmd.arg(buffer),
-                        _ => unreachable!(),
-                    };
-                } else {
-                    req.start.arg(buffer);
-                }
-            },
-            None => {
-                command = Some(CmdReq {
-                    start: process::Command::new(buffer),
-                    chain: None
-                });
-            },
-        };
+}
+
+/// Writes out a suggestion with the letters that matched what was typed standing out
+fn write_matched(stdout: &mut io::Stdout, candidate: &complete::Candidate, plain: ContentStyle, matched: ContentStyle) -> Result<(), String> {
+    let mut run = String::new();
+    let mut run_matched = false;
+    for (idx, ch) in candidate.text.chars().enumerate() {
+        let is_matched = candidate.matched.contains(&idx);
+        if is_matched != run_matched && !run.is_empty() {
+            let style = if run_matched { matched } else { plain };
+            stdout.uswrite(style.apply(std::mem::take(&mut run)))?;
+        }
+        run_matched = is_matched;
+        run.push(ch);
     }
-    
-    return match command {
-        Some(x) => Ok(x),
-        None => Err(format!("Unknown syntax or command: {}", input)),
-    };
+    let style = if run_matched { matched } else { plain };
+    stdout.uswrite(style.apply(run))?;
+    return Ok(());
+}
+
+fn draw_completion_menu(stdout: &mut io::Stdout, layout: &MenuLayout, items: &[complete::Candidate], selected: usize) -> Result<(), String> {
+    // Only a window of rows fits on screen, keep the selected one inside of it
+    let selected_row = selected / layout.columns;
+    let first_row = (selected_row + 1).saturating_sub(layout.visible_rows);
+    for row in first_row..first_row + layout.visible_rows {
+        stdout.uqueue(cursor::MoveToNextLine(1))?;
+        for column in 0..layout.columns {
+            let idx = row * layout.columns + column;
+            let item = match items.get(idx) {
+                Some(x) => x,
+                None => break,
+            };
+            let plain = if idx == selected { ContentStyle::new().black().on_cyan() } else { ContentStyle::new().yellow() };
+            write_matched(stdout, item, plain, plain.bold().underlined())?;
+            let padding = layout.column_width - item.text.chars().count();
+            stdout.ubwrite(" ".repeat(padding))?;
+        }
+    }
+    return Ok(());
 }
 
+
 // TODO: Test this abomination
-fn parse_path(cwd: &path::PathBuf, path: &str) -> Result<path::PathBuf, String> {
-    let mut new_path = cwd.clone();
+/// Turns a path the user typed into a real one, relative paths start at `cwd`. The folders along the way
+/// have to exist, the last bit doesn't, callers check whatever they need from it.
+// The windows check stays its own `if` so the rest of it reads the same on every platform
+#[allow(clippy::collapsible_if)]
+fn parse_path(cwd: &path::Path, path: &str) -> Result<path::PathBuf, String> {
+    let mut new_path = cwd.to_path_buf();
     let mut path = path.replace("\\", "/");
     if cfg!(windows) {
         if path.to_lowercase().starts_with("c:/") {
@@ -585,31 +1031,9 @@ fn parse_path(cwd: &path::PathBuf, path: &str) -> Result<path::PathBuf, String>
         if cfg!(windows) {
             path = format!("C:{}", path);
         }
-        let abs_dir = path::PathBuf::from(&path);
-        if abs_dir.is_symlink() {
-            drop(abs_dir);
-            let (final_path, links_checked) = resolve_symlink(&new_path)?;
-            if !final_path.is_dir() {
-                let mut error = String::from("Can't CD onto non-directory path: {}\n");
-                let mut prev = String::with_capacity(0);
-                for (i, cur) in links_checked.iter().enumerate() {
-                    if i == 0 {
-                        prev = cur.clone();
-                        continue;
-                    }
-                    error = format!("{}  `{}` -> `{}`\n", error, prev, cur);
-                }
-                return Err(error);
-            }
-            return Ok(final_path);
-        }
-
-        if !abs_dir.is_dir() {
-            return Err(format!("Can't CD into non-directory path: {}\n", new_path.display()));
-        }
         // TODO: Need to handle the edge case when the user passed "absolute" path has relative
         // pathing inside of it i.e. /home/usr/personal/ecchi/../anime/./best-animes/evangelion
-        return Ok(abs_dir);
+        return Ok(path::PathBuf::from(&path));
     }
     if path == ".." {
         match new_path.parent() {
@@ -641,29 +1065,31 @@ fn parse_path(cwd: &path::PathBuf, path: &str) -> Result<path::PathBuf, String>
             new_path.push(dir_name);
             continue;
         }
-        let dir_name = String::from(path.drain(..).as_str());
-        let tmp_path = new_path.join(&dir_name);
-        if !tmp_path.exists() {
-            return Err(format!("Can't find directory {}\n", tmp_path.display()));
-        }
-        new_path.push(dir_name);
+        let file_name = String::from(path.drain(..).as_str());
+        new_path.push(file_name);
     }
+    return Ok(new_path);
+}
+
+/// `parse_path` for `cd`, where the path also has to lead to a directory
+fn parse_dir_path(cwd: &path::Path, path: &str) -> Result<path::PathBuf, String> {
+    let new_path = parse_path(cwd, path)?;
     if new_path.is_symlink() {
-        let (final_path, links_checked) = resolve_symlink(new_path.clone().as_path())?;
+        let final_path = match exes::resolve_link(&new_path) {
+            Some(x) => x,
+            None => return Err(format!("Can't follow {}, the link is broken or goes in circles\n", new_path.display())),
+        };
+        if !final_path.exists() {
+            return Err(format!("Can't find directory {} -> {}\n", new_path.display(), final_path.display()));
+        }
         if !final_path.is_dir() {
-            let mut error = String::from("Can't CD onto non-directory path: {}\n");
-            let mut prev = String::with_capacity(0);
-            for (i, cur) in links_checked.iter().enumerate() {
-                if i == 0 {
-                    prev = cur.clone();
-                    continue;
-                }
-                error = format!("{}  `{}` -> `{}`\n", error, prev, cur);
-            }
-            return Err(error);
+            return Err(format!("Can't CD onto non-directory path: {} -> {}\n", new_path.display(), final_path.display()));
         }
         return Ok(final_path);
     }
+    if !new_path.exists() {
+        return Err(format!("Can't find directory {}\n", new_path.display()));
+    }
     if !new_path.is_dir() {
         return Err(format!("Can't CD into non-directory path: {}\n", new_path.display()));
     }
@@ -671,176 +1097,6 @@ fn parse_path(cwd: &path::PathBuf, path: &str) -> Result<path::PathBuf, String>
 }
 
 
-fn resolve_symlink(path: &path::Path) -> Result<(path::PathBuf, Vec<String>), String> {
-    let mut final_path = path.to_path_buf();
-    let mut links_checked = Vec::new();
-    links_checked.push(format!("{}", final_path.display()));
-    while final_path.is_symlink() {
-        match final_path.read_link() {
-            Err(err) => return Err(format!("Failed to read symlink target: {}\n", err)),
-            Ok(linked_path) => {
-                links_checked.push(format!("{}", linked_path.display()));
-                final_path = linked_path;
-            },
-        };
-    }
-    return Ok((final_path, links_checked));
-}
-
-
-fn query_env_exes() -> io::Result<Vec<(path::PathBuf, String)>> {
-    let mut exes = Vec::new();
-    for (key, val) in std::env::vars_os() {
-        // Ignore anything that's not in the PATH variable
-        if key.to_string_lossy().to_string().to_lowercase() != "path" {
-            continue;
-        }
-        
-        let mut paths = Vec::new();
-        let val_string = val.to_string_lossy().to_string();
-        #[cfg(target_family="windows")]
-        const ENV_DELIM:char = ';';
-        #[cfg(target_family="unix")]
-        const ENV_DELIM:char = ':';
-        
-        if val_string.contains(ENV_DELIM) {
-            for path in std::env::split_paths(&val) {
-                if !path.exists() {
-                    continue;
-                }
-                if path.is_file() {
-                    paths.push(path);
-                    continue;
-                }
-                if !path.is_dir() {
-                    continue;
-                }
-                if let Ok(entries) = path.read_dir() {
-                    for entry_res in entries {
-                        if let Ok(entry) = entry_res {
-                            let entry_path = entry.path();
-                            if !entry_path.is_file() {
-                                continue;
-                            }
-                            paths.push(entry_path);
-                        }
-                    }
-                }
-            }
-        } else {
-            let path = path::PathBuf::from(&val);
-            let exists = path.exists();
-            if !exists {
-            } else if path.is_file() {
-                paths.push(path);
-            } else if path.is_dir() {
-                if let Ok(entries) = path.read_dir() {
-                    for entry_res in entries {
-                        if !entry_res.is_ok() {
-                            continue;
-                        }
-                        let entry_path = entry_res.unwrap().path();
-                        if !entry_path.is_file() {
-                            continue;
-                        }
-                        paths.push(entry_path);
-                    }
-                }
-            }
-        }
-        let paths = paths;
-        
-        for path in paths.iter() {
-            // TODO: Inspect symlinks I guess
-            if path.is_symlink() {
-                continue;
-            }
-            #[cfg(target_family="windows")]
-            if path.is_file() {
-                if let Some(ext) = path.extension() {
-                    // If a file don't end with .bat or .exe, it's not worth even checking if it's executable
-                    // If you don't know why, the reason is "windows" trust me
-                    if ext != "bat" && ext != "exe" {
-                        continue;
-                    }
-                }
-            }
-
-            if !path.is_executable() {
-                continue;
-            }
-            
-            match path.file_name() {
-                None => {},
-                Some(os_name) => match os_name.to_str() {
-                    None => {},
-                    Some(name) => {
-                        let name = name.to_string();
-                        // To be honest, I'm not sure which one window's picks as priority .bat or .exe
-                        // so best not to show the file extensions. Also if you have done this, that's
-                        // kinda cringe bro. (I've done this, I need to clean my path variable)
-                        #[cfg(target_family="windows")]
-                        let name = match name.strip_suffix(".exe") {
-                            None =>  name,
-                            Some(stripped_name) => stripped_name.to_string(),
-                        };
-                        #[cfg(target_family="windows")]
-                        let name = match name.strip_suffix(".bat") {
-                            None =>  name,
-                            Some(stripped_name) => stripped_name.to_string(),
-                        };
-                        exes.push((path.to_owned(), name));
-                    },
-                },
-            };
-        }
-    }
-
-    let mut names_added = Vec::new();
-    let exes:Vec<_> = exes.into_iter().filter(|(_, name)| {
-        if names_added.contains(name) {
-            false
-        } else {
-            names_added.push(name.to_string());
-            true
-        }
-    }).collect();
-    
-    return Ok(exes);
-}
-
-fn query_env_cmds() -> Vec<(String, String)> {
-    let mut cmds = Vec::new();
-    let quit_cmds = ["kys", "exit", "quit", "q", "kindness"];
-    for qc in quit_cmds.iter() {
-        cmds.push((qc.to_string(), String::from("QUIT")));
-    }
-    return cmds;
-}
-
-
-
-
-
-// TODO: Is it better to do a search for the .git folder? Did it this way cause it was the easiest and "it just works" - Tod Howard
-fn query_git_branch_name() -> io::Result<Option<String>> {
-    let mut args = Vec::new();
-    args.push("branch".to_string());
-    args.push("--show-current".to_string());
-    return process::Command::new("git")
-        .arg("branch")
-        .arg("--show-current")
-        .output()
-        .map(|o| {
-            if o.status.success() {
-                Some(String::from_utf8_lossy(&o.stdout).trim().to_string())
-            } else {
-                None
-            }
-        });
-}
-
-
 // Not sure if to keep whoami so for now it's placed in this little isolation box we call a function.
 fn query_username() -> Result<String, io::Error> {
     return whoami::username();
@@ -851,7 +1107,7 @@ fn query_current_directory_name() -> Result<(path::PathBuf, String), String> {
     let dir = env::current_dir().iu()?;
     let file_name = match dir.file_name() {
         Some(f) => f.to_string_lossy().to_string(),
-        None => return Err(format!("Unabled to get the current directory's name. This might be because path ends with `..`, but truly IDK why gomena-sorry :(")),
+        None => return Err(String::from("Unabled to get the current directory's name. This might be because path ends with `..`, but truly IDK why gomena-sorry :(")),
     };
     return Ok((dir, file_name));
 }
@@ -865,7 +1121,7 @@ fn query_current_directory_name() -> Result<(path::PathBuf, String), String> {
 
 
 
-/**
+/*
  * ===================================
  * | <Traits Section>
  * | Viewer discretion is adviced
@@ -922,3 +1178,47 @@ impl<TV, TE: Error> IntoU<Result<TV, String>> for Result<TV, TE> {
         self.map_err(|e| format!("{}", e))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch_file(name: &str, content: &[u8]) -> path::PathBuf {
+        let dir = env::temp_dir().join(format!("xeorvi-main-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join(name);
+        std::fs::write(&file, content).unwrap();
+        return file;
+    }
+
+    #[test]
+    fn script_without_shebang_runs_as_xeorvi_script() {
+        let file = scratch_file("plain", b"echo hi\n");
+        let req = script_request(&file).unwrap();
+        assert_eq!(req.get_program(), env::current_exe().unwrap().as_os_str());
+        assert_eq!(req.get_args().collect::<Vec<_>>(), vec![file.as_os_str()]);
+    }
+
+    #[test]
+    fn binary_without_shebang_is_not_a_script() {
+        let file = scratch_file("binary", b"\x7fELF\x00\x01\x02");
+        assert!(script_request(&file).is_none());
+    }
+
+    #[test]
+    fn launcher_kinds_without_shebang_are_not_xeorvi_scripts() {
+        for name in ["run.cmd", "run.bat", "run.ps1"] {
+            let file = scratch_file(name, b"echo hi\n");
+            assert!(script_request(&file).is_none(), "{} shouldn't be run by xeorvi", name);
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn shebang_picks_the_interpreter() {
+        let file = scratch_file("shebang", b"#!/bin/sh -e\necho hi\n");
+        let req = script_request(&file).unwrap();
+        assert_eq!(req.get_program(), "/bin/sh");
+        assert_eq!(req.get_args().collect::<Vec<_>>(), vec![ffi::OsStr::new("-e"), file.as_os_str()]);
+    }
+}
@@ -0,0 +1,356 @@
+// What `echo -e` and `printf` do with backslashes and `%`s.
+
+use std::iter::Peekable;
+use std::str::Chars;
+
+use crate::complete;
+
+fn push_char(out: &mut Vec<u8>, ch: char) {
+    let mut buf = [0; 4];
+    out.extend_from_slice(ch.encode_utf8(&mut buf).as_bytes());
+}
+
+/// Up to `max` digits in `radix`, None when there wasn't even one
+fn take_digits(chars: &mut Peekable<Chars>, radix: u32, max: usize) -> Option<u32> {
+    let mut value:Option<u32> = None;
+    for _ in 0..max {
+        let digit = match chars.peek().and_then(|c| c.to_digit(radix)) {
+            Some(x) => x,
+            None => break,
+        };
+        chars.next();
+        value = Some(value.unwrap_or(0).saturating_mul(radix).saturating_add(digit));
+    }
+    return value;
+}
+
+/// Turns `\n`, `\t`, `\e`, `\x1b`, `\u{1f980}` and friends into what they stand for. Bytes rather than a
+/// string because `\xff` on its own isn't UTF-8. The bool is whether a `\c` showed up, nothing after that
+/// gets printed at all.
+pub fn unescape(s: &str) -> (Vec<u8>, bool) {
+    let mut out = Vec::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            push_char(&mut out, ch);
+            continue;
+        }
+        let esc = match chars.next() {
+            Some(x) => x,
+            None => {
+                out.push(b'\\');
+                break;
+            },
+        };
+        match esc {
+            'n' => out.push(b'\n'),
+            't' => out.push(b'\t'),
+            'r' => out.push(b'\r'),
+            'a' => out.push(0x07),
+            'b' => out.push(0x08),
+            'f' => out.push(0x0c),
+            'v' => out.push(0x0b),
+            'e' | 'E' => out.push(0x1b),
+            '\\' => out.push(b'\\'),
+            'c' => return (out, true),
+            '0' => out.push(take_digits(&mut chars, 8, 3).unwrap_or(0) as u8),
+            'x' => match take_digits(&mut chars, 16, 2) {
+                Some(x) => out.push(x as u8),
+                None => out.extend_from_slice(b"\\x"),
+            },
+            'u' => {
+                // `\u{1f980}` like in Rust, or the `\u00e9` bash takes
+                let code = if chars.peek() == Some(&'{') {
+                    chars.next();
+                    let code = take_digits(&mut chars, 16, 6);
+                    if chars.peek() == Some(&'}') {
+                        chars.next();
+                    }
+                    code
+                } else {
+                    take_digits(&mut chars, 16, 4)
+                };
+                match code {
+                    Some(x) => push_char(&mut out, char::from_u32(x).unwrap_or(char::REPLACEMENT_CHARACTER)),
+                    None => out.extend_from_slice(b"\\u"),
+                };
+            },
+            other => {
+                out.push(b'\\');
+                push_char(&mut out, other);
+            },
+        };
+    }
+    return (out, false);
+}
+
+/// Widths and precisions past this are a typo or somebody poking at the shell, either way not worth
+/// allocating gigabytes of spaces over
+const MAX_WIDTH: usize = 4096;
+
+/// What goes in between the `%` and the conversion
+#[derive(Default)]
+struct Spec {
+    left: bool,
+    zero: bool,
+    plus: bool,
+    alt: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl Spec {
+    /// Fills up to the width, zeroes go after the sign and any `0x`
+    fn pad(&self, s: String, numeric: bool) -> String {
+        let len = s.chars().count();
+        if len >= self.width {
+            return s;
+        }
+        let fill = self.width - len;
+        if self.left {
+            return format!("{}{}", s, " ".repeat(fill));
+        }
+        if self.zero && numeric {
+            let sign_len = if s.starts_with(['-', '+']) { 1 } else { 0 };
+            let prefix_len = if s[sign_len..].starts_with("0x") || s[sign_len..].starts_with("0X") { sign_len + 2 } else { sign_len };
+            return format!("{}{}{}", &s[..prefix_len], "0".repeat(fill), &s[prefix_len..]);
+        }
+        return format!("{}{}", " ".repeat(fill), s);
+    }
+}
+
+/// Numbers the way printf takes them: decimal, `0x` hex, or `'c` for the code of a character
+fn parse_int(arg: &str) -> Option<i64> {
+    let arg = arg.trim();
+    if let Some(ch) = arg.strip_prefix(['\'', '"']).and_then(|x| x.chars().next()) {
+        return Some(ch as i64);
+    }
+    let (negative, digits) = match arg.strip_prefix('-') {
+        Some(x) => (true, x),
+        None => (false, arg.strip_prefix('+').unwrap_or(arg)),
+    };
+    let value = match digits.strip_prefix("0x").or(digits.strip_prefix("0X")) {
+        Some(hex) => i64::from_str_radix(hex, 16).ok()?,
+        None => digits.parse::<i64>().ok()?,
+    };
+    return Some(if negative { -value } else { value });
+}
+
+/// Fills in `format` with `args` like C's printf does. The format gets used again while there are args
+/// left, like every other shell's printf. The errors are for args that didn't fit their conversion, those
+/// still print as a 0 so the output is all there.
+pub fn printf(format: &str, args: &[String]) -> (Vec<u8>, Vec<String>) {
+    let mut out = Vec::new();
+    let mut errors = Vec::new();
+    let mut next = 0;
+    loop {
+        let start = next;
+        let mut rest = format;
+        while !rest.is_empty() {
+            let idx = rest.find('%').unwrap_or(rest.len());
+            let (bytes, stop) = unescape(&rest[..idx]);
+            out.extend_from_slice(&bytes);
+            if stop {
+                return (out, errors);
+            }
+            rest = &rest[idx..];
+            if rest.is_empty() {
+                break;
+            }
+
+            // Flags, width, precision and finally the conversion
+            let mut chars = rest[1..].char_indices().peekable();
+            let mut spec = Spec::default();
+            while let Some((_, flag @ ('-' | '0' | '+' | '#'))) = chars.peek().copied() {
+                match flag {
+                    '-' => spec.left = true,
+                    '0' => spec.zero = true,
+                    '+' => spec.plus = true,
+                    _ => spec.alt = true,
+                };
+                chars.next();
+            }
+            while let Some(digit) = chars.peek().and_then(|(_, c)| c.to_digit(10)) {
+                spec.width = spec.width.saturating_mul(10).saturating_add(digit as usize);
+                chars.next();
+            }
+            if let Some((_, '.')) = chars.peek() {
+                chars.next();
+                let mut precision = 0usize;
+                while let Some(digit) = chars.peek().and_then(|(_, c)| c.to_digit(10)) {
+                    precision = precision.saturating_mul(10).saturating_add(digit as usize);
+                    chars.next();
+                }
+                spec.precision = Some(precision);
+            }
+            if spec.width > MAX_WIDTH || spec.precision.is_some_and(|p| p > MAX_WIDTH) {
+                errors.push(format!("widths and precisions can't go over {}", MAX_WIDTH));
+                spec.width = spec.width.min(MAX_WIDTH);
+                spec.precision = spec.precision.map(|p| p.min(MAX_WIDTH));
+            }
+            let (conv_idx, conv) = match chars.next() {
+                Some(x) => x,
+                None => {
+                    errors.push(format!("`{}` is missing a conversion at the end", rest));
+                    out.extend_from_slice(rest.as_bytes());
+                    break;
+                },
+            };
+            let directive = &rest[..1 + conv_idx + conv.len_utf8()];
+            rest = &rest[directive.len()..];
+
+            if conv == '%' {
+                out.push(b'%');
+                continue;
+            }
+            let arg = args.get(next).map(|x| x.as_str());
+            if arg.is_some() && "sqdixXf".contains(conv) {
+                next += 1;
+            }
+            let formatted = match conv {
+                's' => {
+                    let s = arg.unwrap_or_default();
+                    let s = match spec.precision {
+                        Some(p) => s.chars().take(p).collect(),
+                        None => s.to_string(),
+                    };
+                    spec.pad(s, false)
+                },
+                'q' => {
+                    let s = arg.unwrap_or_default();
+                    let quoted = if s.is_empty() { String::from("''") } else { complete::quote_word(s) };
+                    spec.pad(quoted, false)
+                },
+                'd' | 'i' | 'x' | 'X' => {
+                    let n = match arg.map(parse_int) {
+                        None => 0,
+                        Some(Some(n)) => n,
+                        Some(None) => {
+                            errors.push(format!("`{}` isn't a number", arg.unwrap_or_default()));
+                            0
+                        },
+                    };
+                    let digits = match conv {
+                        'x' => format!("{:x}", n as u64),
+                        'X' => format!("{:X}", n as u64),
+                        _ => n.unsigned_abs().to_string(),
+                    };
+                    let digits = match spec.precision {
+                        Some(p) if digits.len() < p => format!("{}{}", "0".repeat(p - digits.len()), digits),
+                        _ => digits,
+                    };
+                    let prefix = match conv {
+                        'x' if spec.alt && n != 0 => "0x",
+                        'X' if spec.alt && n != 0 => "0X",
+                        'd' | 'i' if n < 0 => "-",
+                        'd' | 'i' if spec.plus => "+",
+                        _ => "",
+                    };
+                    spec.pad(format!("{}{}", prefix, digits), spec.precision.is_none())
+                },
+                'f' => {
+                    let n = match arg.map(|x| x.trim().parse::<f64>()) {
+                        None => 0.0,
+                        Some(Ok(n)) => n,
+                        Some(Err(_)) => {
+                            errors.push(format!("`{}` isn't a number", arg.unwrap_or_default()));
+                            0.0
+                        },
+                    };
+                    let precision = spec.precision.unwrap_or(6);
+                    let s = if spec.plus && n >= 0.0 { format!("+{:.*}", precision, n) } else { format!("{:.*}", precision, n) };
+                    spec.pad(s, n.is_finite())
+                },
+                _ => {
+                    errors.push(format!("`{}` isn't something printf knows how to print", directive));
+                    directive.to_string()
+                },
+            };
+            out.extend_from_slice(formatted.as_bytes());
+        }
+        // Nothing got used this time around, going again would only print the same thing forever
+        if next >= args.len() || next == start {
+            break;
+        }
+    }
+    return (out, errors);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(format: &str, args: &[&str]) -> (String, usize) {
+        let args:Vec<String> = args.iter().map(|x| x.to_string()).collect();
+        let (out, errors) = printf(format, &args);
+        return (String::from_utf8(out).unwrap(), errors.len());
+    }
+
+    #[test]
+    fn unescape_known_escapes() {
+        assert_eq!(unescape(r"a\tb\nc\\d"), (b"a\tb\nc\\d".to_vec(), false));
+        assert_eq!(unescape(r"\e[0m"), (b"\x1b[0m".to_vec(), false));
+        assert_eq!(unescape(r"\x41\x4a\xff"), (vec![b'A', b'J', 0xff], false));
+        assert_eq!(unescape(r"\0101"), (b"A".to_vec(), false));
+        assert_eq!(unescape(r"\u{1f980}é"), ("🦀é".as_bytes().to_vec(), false));
+    }
+
+    #[test]
+    fn unescape_leaves_unknown_and_incomplete_escapes() {
+        assert_eq!(unescape(r"\q"), (b"\\q".to_vec(), false));
+        assert_eq!(unescape(r"\xzz"), (b"\\xzz".to_vec(), false));
+        assert_eq!(unescape(r"\u"), (b"\\u".to_vec(), false));
+        assert_eq!(unescape("end\\"), (b"end\\".to_vec(), false));
+    }
+
+    #[test]
+    fn unescape_stops_at_c() {
+        assert_eq!(unescape(r"one\ctwo"), (b"one".to_vec(), true));
+    }
+
+    #[test]
+    fn printf_conversions() {
+        assert_eq!(run("%s-%d-%i\n", &["a", "12", "-3"]), (String::from("a-12--3\n"), 0));
+        assert_eq!(run("%x %X %#x", &["255", "255", "255"]), (String::from("ff FF 0xff"), 0));
+        assert_eq!(run("%.2f %f", &["3.14159", "1"]), (String::from("3.14 1.000000"), 0));
+        assert_eq!(run("%d", &["'A"]), (String::from("65"), 0));
+        assert_eq!(run("%q", &["a b"]), (String::from("a\\ b"), 0));
+        assert_eq!(run("100%%", &[]), (String::from("100%"), 0));
+    }
+
+    #[test]
+    fn printf_flags_width_and_precision() {
+        assert_eq!(run("[%5s][%-5s]", &["ab", "ab"]), (String::from("[   ab][ab   ]"), 0));
+        assert_eq!(run("[%05d][%+d][%.3d]", &["-42", "7", "5"]), (String::from("[-0042][+7][005]"), 0));
+        assert_eq!(run("[%#06x]", &["255"]), (String::from("[0x00ff]"), 0));
+        assert_eq!(run("[%.2s]", &["abcdef"]), (String::from("[ab]"), 0));
+    }
+
+    #[test]
+    fn printf_reuses_the_format() {
+        assert_eq!(run("<%s>", &["a", "b", "c"]), (String::from("<a><b><c>"), 0));
+        assert_eq!(run("%s=%s ", &["a", "1", "b"]), (String::from("a=1 b= "), 0));
+        // No conversions means nothing to use the args up on
+        assert_eq!(run("hi\n", &["a", "b"]), (String::from("hi\n"), 0));
+    }
+
+    #[test]
+    fn printf_bad_args_print_zero_and_error() {
+        assert_eq!(run("%d %f", &["x", "y"]), (String::from("0 0.000000"), 2));
+        assert_eq!(run("%z", &[]), (String::from("%z"), 1));
+        assert_eq!(run("abc %", &[]), (String::from("abc %"), 1));
+    }
+
+    #[test]
+    fn printf_stops_at_c() {
+        assert_eq!(run("a\\cb%s", &["x"]), (String::from("a"), 0));
+    }
+
+    #[test]
+    fn printf_huge_width_and_precision_are_capped() {
+        let (out, errors) = run("%99999999999999999999999s", &["x"]);
+        assert_eq!((out.len(), errors), (MAX_WIDTH, 1));
+        let (out, errors) = run("%.99999999999999999999f", &["1"]);
+        assert_eq!((out.len(), errors), (MAX_WIDTH + 2, 1));
+    }
+}
@@ -0,0 +1,362 @@
+// Prompt templates, think PS1 but with braces.
+//
+//   {name}     expands to a piece of shell state, see `PromptInfo::value` for the list
+//   {cyan}     colours and attributes change the style of whatever comes after them, see `apply_style`
+//   {reset}    back to the terminal's default style
+//   {fill:═}   pads the line up to the terminal's width with the given character, only one per template
+//   [ ... ]    only shows up when every `{name}` inside of it expanded to something
+//   \x         a literal x, for when you need a `{` or `[` in your prompt
+
+use crossterm::style::{ContentStyle, StyledContent, Stylize};
+
+/// The bar drawn above the input line, override with `XEORVI_PROMPT_TOP`
+pub const DEFAULT_TOP_TEMPLATE: &str = "{cyan}{on_black}╔┈{cwd}/┈{fill:═}";
+/// Drawn right before what the user is typing, override with `XEORVI_PROMPT`
+pub const DEFAULT_LINE_TEMPLATE: &str = concat!(
+    "{cyan}{on_black}╠┈{reset}{white}{on_black}«{user}»{reset}",
+    "[{red}{on_black}┈Git({white}{git}{red})]{reset}",
+    "[{yellow}{on_black}┈⧗{duration}]{reset}",
+    "[{red}{on_black}┈{fail}]{reset}",
+    "{status_color}∑◈{reset} ",
+);
+
+/// Everything a template can show, gathered right before the prompt is drawn
+pub struct PromptInfo {
+    pub cwd: String,
+    pub path: String,
+    pub user: String,
+    pub host: String,
+    pub git: String,
+    pub git_commit: String,
+    pub status: i32,
+    /// Already formatted and only set when the last command took long enough to be worth showing
+    pub duration: Option<String>,
+    pub jobs: usize,
+}
+
+impl PromptInfo {
+    fn value(&self, name: &str) -> Option<String> {
+        let value = match name {
+            "cwd" => self.cwd.clone(),
+            "path" => self.path.clone(),
+            "user" => self.user.clone(),
+            "host" => self.host.clone(),
+            "git" => self.git.clone(),
+            "git_commit" => self.git_commit.clone(),
+            "status" => self.status.to_string(),
+            "fail" => if self.status == 0 { String::new() } else { self.status.to_string() },
+            "duration" => self.duration.clone().unwrap_or_default(),
+            "time" => query_local_time(),
+            "jobs" => if self.jobs == 0 { String::new() } else { self.jobs.to_string() },
+            _ => return None,
+        };
+        return Some(value);
+    }
+}
+
+enum Piece {
+    Text(String),
+    Value(String),
+    Style(String),
+    Fill(char),
+    Group(Vec<Piece>),
+}
+
+fn parse(chars: &mut std::str::Chars, in_group: bool) -> Vec<Piece> {
+    let mut pieces = Vec::new();
+    let mut text = String::new();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => {
+                if let Some(nch) = chars.next() {
+                    text.push(nch);
+                }
+                continue;
+            },
+            ']' if in_group => break,
+            '[' | '{' => {},
+            _ => {
+                text.push(ch);
+                continue;
+            },
+        };
+        if !text.is_empty() {
+            pieces.push(Piece::Text(std::mem::take(&mut text)));
+        }
+        if ch == '[' {
+            pieces.push(Piece::Group(parse(chars, true)));
+            continue;
+        }
+        let mut name = String::new();
+        let mut closed = false;
+        for nch in chars.by_ref() {
+            if nch == '}' {
+                closed = true;
+                break;
+            }
+            name.push(nch);
+        }
+        if !closed {
+            // Not a placeholder after all, show it as typed so the mistake is easy to spot
+            pieces.push(Piece::Text(format!("{{{}", name)));
+            continue;
+        }
+        if let Some(fill) = name.strip_prefix("fill:") {
+            pieces.push(Piece::Fill(fill.chars().next().unwrap_or(' ')));
+        } else if name == "fill" {
+            pieces.push(Piece::Fill(' '));
+        } else if apply_style(ContentStyle::new(), &name, 0).is_some() {
+            pieces.push(Piece::Style(name));
+        } else {
+            pieces.push(Piece::Value(name));
+        }
+    }
+    if !text.is_empty() {
+        pieces.push(Piece::Text(text));
+    }
+    return pieces;
+}
+
+fn apply_style(style: ContentStyle, name: &str, status: i32) -> Option<ContentStyle> {
+    let style = match name {
+        "reset" => ContentStyle::new(),
+        "status_color" => if status == 0 { style.green() } else { style.red() },
+        "bold" => style.bold(),
+        "dim" => style.dim(),
+        "italic" => style.italic(),
+        "underlined" => style.underlined(),
+        "reverse" => style.reverse(),
+        "black" => style.black(),
+        "dark_grey" => style.dark_grey(),
+        "red" => style.red(),
+        "dark_red" => style.dark_red(),
+        "green" => style.green(),
+        "dark_green" => style.dark_green(),
+        "yellow" => style.yellow(),
+        "dark_yellow" => style.dark_yellow(),
+        "blue" => style.blue(),
+        "dark_blue" => style.dark_blue(),
+        "magenta" => style.magenta(),
+        "dark_magenta" => style.dark_magenta(),
+        "cyan" => style.cyan(),
+        "dark_cyan" => style.dark_cyan(),
+        "white" => style.white(),
+        "grey" => style.grey(),
+        "on_black" => style.on_black(),
+        "on_dark_grey" => style.on_dark_grey(),
+        "on_red" => style.on_red(),
+        "on_dark_red" => style.on_dark_red(),
+        "on_green" => style.on_green(),
+        "on_dark_green" => style.on_dark_green(),
+        "on_yellow" => style.on_yellow(),
+        "on_dark_yellow" => style.on_dark_yellow(),
+        "on_blue" => style.on_blue(),
+        "on_dark_blue" => style.on_dark_blue(),
+        "on_magenta" => style.on_magenta(),
+        "on_dark_magenta" => style.on_dark_magenta(),
+        "on_cyan" => style.on_cyan(),
+        "on_dark_cyan" => style.on_dark_cyan(),
+        "on_white" => style.on_white(),
+        "on_grey" => style.on_grey(),
+        _ => return None,
+    };
+    return Some(style);
+}
+
+struct Renderer<'a> {
+    info: &'a PromptInfo,
+    out: Vec<StyledContent<String>>,
+    style: ContentStyle,
+    /// Where the fill goes in `out` and which character to pad with
+    fill: Option<(usize, char)>,
+}
+
+impl Renderer<'_> {
+    /// Returns false when one of the values expanded to nothing, which hides the group it's in
+    fn render(&mut self, pieces: &[Piece]) -> bool {
+        let mut all_present = true;
+        for piece in pieces.iter() {
+            match piece {
+                Piece::Text(text) => self.out.push(StyledContent::new(self.style, text.clone())),
+                Piece::Value(name) => match self.info.value(name) {
+                    Some(value) => {
+                        if value.is_empty() {
+                            all_present = false;
+                        }
+                        self.out.push(StyledContent::new(self.style, value));
+                    },
+                    None => self.out.push(StyledContent::new(self.style, format!("{{{}}}", name))),
+                },
+                Piece::Style(name) => {
+                    if let Some(style) = apply_style(self.style, name, self.info.status) {
+                        self.style = style;
+                    }
+                },
+                Piece::Fill(ch) => {
+                    if self.fill.is_none() {
+                        self.fill = Some((self.out.len(), *ch));
+                        self.out.push(StyledContent::new(self.style, String::new()));
+                    }
+                },
+                Piece::Group(inner) => {
+                    let (len, style, fill) = (self.out.len(), self.style, self.fill);
+                    if !self.render(inner) {
+                        self.out.truncate(len);
+                        self.style = style;
+                        self.fill = fill;
+                    }
+                },
+            };
+        }
+        return all_present;
+    }
+}
+
+/// Renders a template into styled chunks ready to be written out, `cols` is only used for `{fill}`
+pub fn render(template: &str, info: &PromptInfo, cols: u16) -> Vec<StyledContent<String>> {
+    let pieces = parse(&mut template.chars(), false);
+    let mut renderer = Renderer { info, out: Vec::new(), style: ContentStyle::new(), fill: None };
+    renderer.render(&pieces);
+    let mut out = renderer.out;
+    if let Some((idx, ch)) = renderer.fill {
+        let used:usize = out.iter().map(|x| x.content().chars().count()).sum();
+        let amount = (cols as usize).saturating_sub(used);
+        out[idx] = StyledContent::new(*out[idx].style(), ch.to_string().repeat(amount));
+    }
+    return out;
+}
+
+#[cfg(unix)]
+fn query_local_time() -> String {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0) as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    // SAFETY: both pointers are valid for the duration of the call
+    if unsafe { libc::localtime_r(&now, &mut tm) }.is_null() {
+        return String::new();
+    }
+    return format!("{:02}:{:02}:{:02}", tm.tm_hour, tm.tm_min, tm.tm_sec);
+}
+
+// No timezone database in std so UTC it is outside of unix
+#[cfg(not(unix))]
+fn query_local_time() -> String {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    return format!("{:02}:{:02}:{:02}", (secs / 3600) % 24, (secs / 60) % 60, secs % 60);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::style::Color;
+
+    fn info() -> PromptInfo {
+        return PromptInfo {
+            cwd: String::from("~/src"),
+            path: String::from("/home/me/src"),
+            user: String::from("me"),
+            host: String::from("box"),
+            git: String::new(),
+            git_commit: String::new(),
+            status: 0,
+            duration: None,
+            jobs: 0,
+        };
+    }
+
+    /// The rendered prompt without any of the styling
+    fn plain(template: &str, info: &PromptInfo, cols: u16) -> String {
+        return render(template, info, cols).iter().map(|x| x.content().as_str()).collect();
+    }
+
+    #[test]
+    fn parse_splits_into_pieces() {
+        let pieces = parse(&mut "{red}hi {user}[{git}]{fill:-}".chars(), false);
+        assert!(matches!(pieces.as_slice(), [
+            Piece::Style(style),
+            Piece::Text(text),
+            Piece::Value(value),
+            Piece::Group(group),
+            Piece::Fill('-'),
+        ] if style == "red" && text == "hi " && value == "user" && matches!(group.as_slice(), [Piece::Value(x)] if x == "git")));
+    }
+
+    #[test]
+    fn unknown_placeholders_show_as_typed() {
+        let info = info();
+        assert_eq!(plain("{user}@{nope}", &info, 80), "me@{nope}");
+        // An unknown name isn't an empty one, the group stays
+        assert_eq!(plain("[<{nope}>]", &info, 80), "<{nope}>");
+        assert_eq!(plain("{user} {cwd", &info, 80), "me {cwd");
+    }
+
+    #[test]
+    fn escapes_are_literal() {
+        let info = info();
+        assert_eq!(plain("\\{user\\}", &info, 80), "{user}");
+        assert_eq!(plain("\\[{user}\\]", &info, 80), "[me]");
+        assert_eq!(plain("a\\\\b", &info, 80), "a\\b");
+        assert_eq!(plain("trailing\\", &info, 80), "trailing");
+    }
+
+    #[test]
+    fn groups_hide_without_a_value() {
+        let mut info = info();
+        assert_eq!(plain("a[ git:{git}]b", &info, 80), "ab");
+        assert_eq!(plain("a[ {fail} [{jobs}]]b", &info, 80), "ab");
+        info.status = 1;
+        info.jobs = 2;
+        info.git = String::from("main");
+        assert_eq!(plain("a[ git:{git}]b", &info, 80), "a git:mainb");
+        assert_eq!(plain("a[ {fail} [{jobs}]]b", &info, 80), "a 1 2b");
+        info.jobs = 0;
+        assert_eq!(plain("a[ {fail} [{jobs}]]b", &info, 80), "a 1 b");
+    }
+
+    #[test]
+    fn hidden_groups_leave_the_style_alone() {
+        let info = info();
+        let out = render("{blue}[{red}{git}]x", &info, 80);
+        assert_eq!(out.last().unwrap().content(), "x");
+        assert_eq!(out.last().unwrap().style().foreground_color, Some(Color::Blue));
+        let out = render("[{red}{user}]x", &info, 80);
+        assert_eq!(out.last().unwrap().style().foreground_color, Some(Color::Red));
+    }
+
+    #[test]
+    fn status_color_follows_the_last_status() {
+        let mut info = info();
+        assert_eq!(render("{status_color}$", &info, 80)[0].style().foreground_color, Some(Color::Green));
+        info.status = 127;
+        assert_eq!(render("{status_color}$", &info, 80)[0].style().foreground_color, Some(Color::Red));
+        assert_eq!(render("{red}{reset}$", &info, 80)[0].style().foreground_color, None);
+    }
+
+    #[test]
+    fn fill_pads_to_the_width() {
+        let info = info();
+        assert_eq!(plain("{cwd}{fill:═}|", &info, 10), "~/src════|");
+        assert_eq!(plain("{cwd}{fill}|", &info, 10), "~/src    |");
+        // Only the first fill counts, and a line that's already too long doesn't get any
+        assert_eq!(plain("{fill:-}{cwd}{fill:=}", &info, 8), "---~/src");
+        assert_eq!(plain("{path}{fill:-}", &info, 4), "/home/me/src");
+        // A fill in a hidden group is gone with the rest of it
+        assert_eq!(plain("[{git}{fill:-}]{cwd}", &info, 8), "~/src");
+    }
+
+    #[test]
+    fn default_templates_render() {
+        let mut info = info();
+        assert_eq!(plain(DEFAULT_LINE_TEMPLATE, &info, 80), "╠┈«me»∑◈ ");
+        info.git = String::from("main +1");
+        info.duration = Some(String::from("3s"));
+        info.status = 1;
+        assert_eq!(plain(DEFAULT_LINE_TEMPLATE, &info, 80), "╠┈«me»┈Git(main +1)┈⧗3s┈1∑◈ ");
+        assert_eq!(plain(DEFAULT_TOP_TEMPLATE, &info, 12).chars().count(), 12);
+    }
+}
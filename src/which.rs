@@ -0,0 +1,123 @@
+// `type`, `which` and `command`: finding out what a name is going to run.
+
+use crossterm::style::Stylize;
+
+use crate::builtins::{self, Io};
+use crate::exes::Exe;
+use crate::{Shell, UWrite};
+
+/// Something a command name can end up running, in the order they're tried
+pub enum Found {
+    Function,
+    Builtin,
+    Program(Exe),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Style {
+    /// `name is a shell builtin`
+    Type,
+    /// Just the path, like the usual `which`
+    Which,
+    /// `command -v`, the path or the name itself for anything that isn't a program
+    Short,
+}
+
+impl Shell {
+    /// What `name` would run, only the first match unless `all` is set. `functions` is off for `command`.
+    pub fn lookup_command(&self, name: &str, all: bool, functions: bool) -> Vec<Found> {
+        let mut found = Vec::new();
+        if name.contains('/') {
+            let Ok(path) = crate::parse_path(&self.dir_path, name) else {
+                return found;
+            };
+            if path.is_file() && is_executable::is_executable(&path) {
+                found.push(Found::Program(Exe { path, target: None }));
+            }
+            return found;
+        }
+        if functions && self.find_function(name).is_some() {
+            found.push(Found::Function);
+        }
+        if builtins::find(name, self.name_case()).is_some() {
+            found.push(Found::Builtin);
+        }
+        if !all {
+            if found.is_empty() {
+                if let Some(exe) = self.env_exes.get(name) {
+                    found.push(Found::Program(exe.clone()));
+                }
+            }
+            found.truncate(1);
+            return found;
+        }
+        for exe in self.env_exes.find_all(name) {
+            found.push(Found::Program(exe));
+        }
+        return found;
+    }
+
+    fn describe_commands(&mut self, names: &[String], all: bool, functions: bool, style: Style, io: &mut Io) -> Result<i32, String> {
+        let mut missing = false;
+        for name in names.iter() {
+            let found = self.lookup_command(name, all, functions);
+            if found.is_empty() {
+                missing = true;
+                if style != Style::Short {
+                    io.stderr.uswrite("[?ERROR]".red())?;
+                    io.stderr.ubwrite(format!(" {}: not found\n", name))?;
+                }
+                continue;
+            }
+            for item in found.iter() {
+                let line = match (item, style) {
+                    (Found::Function, Style::Type) => format!("{} is a function", name),
+                    (Found::Function, Style::Which) => format!("{}: shell function", name),
+                    (Found::Builtin, Style::Type) => format!("{} is a shell builtin", name),
+                    (Found::Builtin, Style::Which) => format!("{}: shell builtin", name),
+                    (Found::Function | Found::Builtin, Style::Short) => name.clone(),
+                    (Found::Program(exe), Style::Type) => match &exe.target {
+                        Some(target) => format!("{} is {} -> {}", name, exe.path.display(), target.display()),
+                        None => format!("{} is {}", name, exe.path.display()),
+                    },
+                    (Found::Program(exe), Style::Which) => match &exe.target {
+                        Some(target) => format!("{} -> {}", exe.path.display(), target.display()),
+                        None => exe.path.display().to_string(),
+                    },
+                    (Found::Program(exe), Style::Short) => exe.path.display().to_string(),
+                };
+                io.stdout.ubwrite(format!("{}\n", line))?;
+            }
+        }
+        return Ok(if missing { 1 } else { 0 });
+    }
+
+    /// `type [-a] name...` and `which [-a] name...`
+    pub fn type_builtin(&mut self, argv: &[String], io: &mut Io) -> Result<i32, String> {
+        let style = if self.name_case().key(&argv[0]) == "which" { Style::Which } else { Style::Type };
+        let all = argv.get(1).is_some_and(|x| x == "-a");
+        let names = &argv[if all { 2 } else { 1 }..];
+        if names.is_empty() {
+            return builtins::usage_error(io, "type");
+        }
+        return self.describe_commands(names, all, true, style, io);
+    }
+
+    /// `command [-v|-V] name [args...]` runs a builtin or program even when a function has the same name
+    pub fn command_builtin(&mut self, argv: &[String], env_vars: &[(String, String)], io: &mut Io) -> Result<i32, String> {
+        let style = match argv.get(1).map(|x| x.as_str()) {
+            Some("-v") => Some(Style::Short),
+            Some("-V") => Some(Style::Type),
+            _ => None,
+        };
+        let rest = &argv[if style.is_some() { 2 } else { 1 }..];
+        if rest.is_empty() {
+            return builtins::usage_error(io, "command");
+        }
+        if let Some(style) = style {
+            return self.describe_commands(rest, false, false, style, io);
+        }
+        self.run_command(rest.to_vec(), env_vars.to_vec())?;
+        return Ok(self.last_status);
+    }
+}